@@ -44,17 +44,22 @@ impl<T: Clone> Arena<T> {
         unsafe { &*slice_from_raw_parts(&self.current[start], len) }
     }
 
+    /// Everything pushed so far in the order it was pushed, so an item keeps
+    /// its index across views.
     pub fn read_only_view(&self) -> Vec<&'static [T]> {
         let mut slices = Vec::new();
 
-        let slice = unsafe { &*slice_from_raw_parts(&self.current[0], self.current.len()) };
-        slices.push(slice);
-
         for slice in &self.full {
             if slice.is_empty() { break; }
             let raw_slice = unsafe { &*slice_from_raw_parts(&slice[0], slice.len()) };
             slices.push(raw_slice);
         }
+
+        // a storage can still be empty when a slow source hasn't reached it yet
+        if !self.current.is_empty() {
+            let slice = unsafe { &*slice_from_raw_parts(&self.current[0], self.current.len()) };
+            slices.push(slice);
+        }
         
         slices
     }
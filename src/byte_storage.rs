@@ -1,4 +1,4 @@
-use std::{fmt::Display, path::{PathBuf, MAIN_SEPARATOR}, str::FromStr};
+use std::{fmt::Display, path::{PathBuf, MAIN_SEPARATOR}};
 
 
 use crate::arena::Arena;
@@ -6,15 +6,97 @@ use crate::arena::Arena;
 const ALLOC_SIZE: usize = 100_000;
 // const ALLOC_SIZE: usize = 1000;
 
+// bits of a `--type` filter, an empty filter lets everything through
+pub const TYPE_FILE: u8 = 1;
+pub const TYPE_DIR: u8 = 1 << 1;
+pub const TYPE_SYMLINK: u8 = 1 << 2;
+pub const TYPE_EXECUTABLE: u8 = 1 << 3;
+
+pub fn type_filter_bit(name: &str) -> Option<u8> {
+    match name {
+        "f" | "file" => Some(TYPE_FILE),
+        "d" | "dir" | "directory" => Some(TYPE_DIR),
+        "l" | "symlink" => Some(TYPE_SYMLINK),
+        "x" | "executable" => Some(TYPE_EXECUTABLE),
+        _ => None,
+    }
+}
+
+pub fn type_filter_name(filter: u8) -> String {
+    if filter == 0 {
+        return "all".into();
+    }
+    [(TYPE_FILE, "f"), (TYPE_DIR, "d"), (TYPE_SYMLINK, "l"), (TYPE_EXECUTABLE, "x")]
+        .iter()
+        .filter(|(bit, _)| filter & bit != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Set of the bytes in `bytes`, folded to 64 bits: one per ASCII letter
+/// ignoring case, one per digit and the rest of ASCII sharing the remaining
+/// bits. Only ever tells for sure that a byte is missing.
+pub fn letter_mask(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |mask, byte| {
+        let bit = match byte {
+            b'a'..=b'z' => byte - b'a',
+            b'A'..=b'Z' => byte - b'A',
+            b'0'..=b'9' => byte - b'0' + 26,
+            0..=0x7f => byte % 28 + 36,
+            // utf-8 bytes aren't matched one by one anyway
+            _ => return mask,
+        };
+        mask | 1 << bit
+    })
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FileKind {
+    File,
+    /// only detected when the walker was asked to, otherwise these are `File`s
+    Executable,
+    Dir,
+    Symlink,
+    Other,
+}
+
+impl FileKind {
+    pub fn matches(self, type_filter: u8) -> bool {
+        let bits = match self {
+            FileKind::File => TYPE_FILE,
+            FileKind::Executable => TYPE_FILE | TYPE_EXECUTABLE,
+            FileKind::Dir => TYPE_DIR,
+            FileKind::Symlink => TYPE_SYMLINK,
+            FileKind::Other => 0,
+        };
+        type_filter == 0 || bits & type_filter != 0
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub struct FilePath {
     pub data: &'static [u8],
+    /// the entry or one of its parents below the walk root is a dotfile
+    pub hidden: bool,
+    pub kind: FileKind,
+    /// index of the walk root the entry was found under
+    pub root: u16,
+    /// position in the order a line source produced its items, 0 for walked entries
+    pub index: u32,
+    /// `letter_mask` of the path, computed once so queries can skip paths cheaply
+    pub letters: u64,
 }
 
 impl FilePath {
     pub fn new(data: &'static [u8]) -> Self {
         Self {
             data,
+            hidden: false,
+            kind: FileKind::File,
+            root: 0,
+            index: 0,
+            letters: letter_mask(data),
         }
     }
 
@@ -27,6 +109,13 @@ impl FilePath {
         unsafe { std::str::from_utf8_unchecked(self.data) }
     }
 
+    /// Path with the walk root it was found under stripped.
+    pub fn relative_path(&self, root: &str) -> &str {
+        let path = self.path();
+        let relative = path.strip_prefix(root).unwrap_or(path);
+        relative.strip_prefix(MAIN_SEPARATOR).unwrap_or(relative)
+    }
+
     pub fn file_ext(&self) -> &str {
         for (ch, i) in self.data.iter().rev().zip((0..self.data.len()).rev()) {
             if *ch as char == MAIN_SEPARATOR {
@@ -40,9 +129,9 @@ impl FilePath {
     }
 
     pub fn containing_folder(&self) -> String {
-        let Ok(mut path) =  PathBuf::from_str(self.path()) else { return "".into() };
+        let mut path = PathBuf::from(self.path());
         if path.is_dir() {
-            return self.clone().into()
+            return (*self).into()
         }
         if path.pop() {
             return path.to_string_lossy().to_string()
@@ -89,9 +178,9 @@ impl Display for FilePath {
     }
 }
 
-impl Into<String> for FilePath {
-    fn into(self) -> String {
-        self.path().into()
+impl From<FilePath> for String {
+    fn from(val: FilePath) -> Self {
+        val.path().into()
     }
 }
 
@@ -179,8 +268,6 @@ impl<'a> Iterator for RefStorageIterator<'a> {
 
 #[cfg(test)]
 mod tests {
-    use std::str::FromStr;
-
     use super::*;
 
     #[test]
@@ -194,6 +281,19 @@ mod tests {
         assert_eq!(parts, vec![b"home".as_ref(), b"paavo", b".config", b"i3", b"config"]);
     }
 
+    #[test]
+    fn test_letter_mask() {
+        let path = letter_mask(b"./src/Main_2.rs");
+        let covered = |text: &[u8]| path & letter_mask(text) == letter_mask(text);
+
+        assert!(covered(b"main"));
+        assert!(covered(b"MAIN"));
+        assert!(covered(b"m_2.rs"));
+        assert!(!covered(b"mainz"));
+        assert!(!covered(b"main3"));
+        assert_eq!(letter_mask("ä".as_bytes()), 0);
+    }
+
     // #[test]
     // fn test_bytestorage() {
     //     let path = "/path/to/some/file";
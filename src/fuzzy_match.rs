@@ -1,60 +1,436 @@
 use crate::byte_storage::FilePath;
 
-const GAP_PENALTY: i32 = 20;
-const BONUS_FIRST_LETTER: i32 = 20;
-const BONUS_RIGHT_LETTER: i32 = 10;
+#[cfg(feature = "simd")]
+mod simd;
 
+// stands in for "no alignment ends here", low enough to never win but
+// far enough from i32::MIN to subtract penalties from
+const UNREACHABLE: i32 = i32::MIN / 2;
+// bound on every scoring weight, so no sum of them over a long path or query
+// can overflow an i32
+const MAX_WEIGHT: i32 = 10_000;
+
+// how a cell was reached, kept only when tracing matched positions
+// the match in this cell extends a match in the cell up left
+const FROM_CONSECUTIVE: u8 = 1;
+// the best alignment so far skips this byte of the path
+const FROM_GAP: u8 = 1 << 1;
+// the gap starts at this byte, right after a match
+const GAP_OPENED: u8 = 1 << 2;
+
+/// Weights of the fuzzy scorer, see `--scoring`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ScoringConfig {
+    /// cost of the first path byte skipped between two matched bytes
+    pub gap_open: i32,
+    /// cost of every further skipped byte of the same gap
+    pub gap_extend: i32,
+    /// every matched byte
+    pub match_letter: i32,
+    /// the first byte of the query, whatever it matched
+    pub first_letter: i32,
+    /// a matched byte right after the previous matched byte
+    pub consecutive: i32,
+    /// extra points for matching a byte at these positions of the path
+    pub segment_start: i32,
+    pub boundary: i32,
+    pub camel_case: i32,
+    pub basename: i32,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        Self {
+            gap_open: 3,
+            gap_extend: 1,
+            match_letter: 10,
+            first_letter: 20,
+            consecutive: 12,
+            segment_start: 15,
+            boundary: 10,
+            camel_case: 10,
+            basename: 8,
+        }
+    }
+}
+
+impl ScoringConfig {
+    /// Overrides weights from a comma separated list like `gap-open=5,basename=0`.
+    pub fn apply(&mut self, list: &str) -> Result<(), String> {
+        for setting in list.split(',').map(str::trim).filter(|setting| !setting.is_empty()) {
+            let (name, value) = setting.split_once('=').ok_or_else(|| format!("expected name=value: {}", setting))?;
+            let value = value.trim().parse().map_err(|_| format!("invalid scoring value: {}", setting))?;
+            if !(0..=MAX_WEIGHT).contains(&value) {
+                return Err(format!("scoring weight out of range 0..={}: {}", MAX_WEIGHT, setting));
+            }
+            let weight = match name.trim() {
+                "gap-open" => &mut self.gap_open,
+                "gap-extend" => &mut self.gap_extend,
+                "match" => &mut self.match_letter,
+                "first-letter" => &mut self.first_letter,
+                "consecutive" => &mut self.consecutive,
+                "segment-start" => &mut self.segment_start,
+                "boundary" => &mut self.boundary,
+                "camel-case" => &mut self.camel_case,
+                "basename" => &mut self.basename,
+                other => return Err(format!("unknown scoring weight: {}", other)),
+            };
+            *weight = value;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CaseMatching {
+    /// case-insensitive unless the query has an uppercase letter
+    Smart,
+    Ignore,
+    Respect,
+}
+
+impl CaseMatching {
+    pub fn ignores_case(self, input: &[u8]) -> bool {
+        match self {
+            CaseMatching::Smart => !input.iter().any(u8::is_ascii_uppercase),
+            CaseMatching::Ignore => true,
+            CaseMatching::Respect => false,
+        }
+    }
+}
+
+/// Where the traceback of `match_positions` currently is.
+#[derive(Copy, Clone)]
+enum Trace {
+    /// in `matches`, the column is a matched byte
+    Match,
+    /// in `best`, either a match or a gap
+    Best,
+    /// in a gap, the column is a skipped byte
+    Gap,
+}
+
+fn bytes_eq(a: u8, b: u8, ignore_case: bool) -> bool {
+    a == b || (ignore_case && a.eq_ignore_ascii_case(&b))
+}
+
+/// Scores how well a query matches a path. Every byte of the query has to be
+/// found in the path, in order, otherwise the score is 0. Matches score more
+/// at word boundaries and in the file name, and skipped path bytes between
+/// two matched ones cost points, opening a gap more than extending it.
 pub struct FuzzyMatcher {
-    scoring_matrix: Vec<i32>,
+    /// best score of an alignment of the query up to the row that matches the
+    /// byte of the path at the column
+    matches: Vec<i32>,
+    /// best score of an alignment of the query up to the row anywhere in the
+    /// path up to the column
+    best: Vec<i32>,
+    directions: Vec<u8>,
+    /// position bonus of every byte of the path being scored
+    bonuses: Vec<i32>,
+    /// scratch space of the vectorized kernel
+    #[cfg(feature = "simd")]
+    simd_buffer: Vec<i32>,
+    case_matching: CaseMatching,
+    scoring: ScoringConfig,
 }
 
 impl FuzzyMatcher {
-    pub fn new() -> Self {
+    pub fn new(case_matching: CaseMatching, scoring: ScoringConfig) -> Self {
         Self {
-            scoring_matrix: Vec::new(),
+            matches: Vec::new(),
+            best: Vec::new(),
+            directions: Vec::new(),
+            bonuses: Vec::new(),
+            #[cfg(feature = "simd")]
+            simd_buffer: Vec::new(),
+            case_matching,
+            scoring,
         }
     }
 
+    pub fn ignores_case(&self, input: &[u8]) -> bool {
+        self.case_matching.ignores_case(input)
+    }
+
     pub fn smith_waterman(&mut self, input: &[u8], to_match: FilePath) -> i32 {
         if let Some(points) = self.instant_match(input, to_match) {
             return points;
         }
-        let to_match = to_match.data;
-        self.scoring_matrix.fill(0);
-        self.scoring_matrix.resize((input.len() + 1) * (to_match.len() + 1), 0);
+        self.score(input, to_match.data, false).0
+    }
 
-        let mut max = 0;
-        let mut gap_length = 0;
+    /// Byte positions in the path that `input` matched, in ascending order.
+    /// Runs the scoring again with a traceback so only use it for rows that are shown.
+    pub fn match_positions(&mut self, input: &[u8], to_match: FilePath) -> Vec<usize> {
+        if let Some(start) = self.instant_match_start(input, to_match) {
+            return (start..start + input.len()).collect();
+        }
+
+        let stride = to_match.data.len() + 1;
+        let (points, mut col) = self.score(input, to_match.data, true);
+        if points == 0 {
+            return Vec::new();
+        }
 
+        // walks back from the last matched byte, every row matches exactly one column
+        let mut positions = Vec::with_capacity(input.len());
+        let mut row = input.len();
+        let mut state = Trace::Match;
+        while row > 0 {
+            let directions = self.directions[row * stride + col];
+            state = match state {
+                Trace::Match => {
+                    positions.push(col - 1);
+                    row -= 1;
+                    col -= 1;
+                    if directions & FROM_CONSECUTIVE != 0 { Trace::Match } else { Trace::Best }
+                }
+                Trace::Best if directions & FROM_GAP != 0 => Trace::Gap,
+                Trace::Best => Trace::Match,
+                Trace::Gap => {
+                    col -= 1;
+                    if directions & GAP_OPENED != 0 { Trace::Match } else { Trace::Gap }
+                }
+            };
+        }
+
+        positions.reverse();
+        positions
+    }
+
+    /// Returns the score and the column of the path byte the last query byte matched.
+    fn score(&mut self, input: &[u8], to_match: &[u8], trace: bool) -> (i32, usize) {
+        self.fill_bonuses(to_match);
+        #[cfg(feature = "simd")]
+        if !trace {
+            let ignore_case = self.case_matching.ignores_case(input);
+            if let Some(result) = simd::score(input, to_match, &self.bonuses, &self.scoring, ignore_case, &mut self.simd_buffer) {
+                return result;
+            }
+        }
+        self.scalar_score(input, to_match, trace)
+    }
+
+    /// `score` one cell at a time, expects the bonuses of `to_match` to be filled.
+    fn scalar_score(&mut self, input: &[u8], to_match: &[u8], trace: bool) -> (i32, usize) {
+        let stride = to_match.len() + 1;
+        let len = (input.len() + 1) * stride;
+        self.matches.clear();
+        self.matches.resize(len, UNREACHABLE);
+        self.best.clear();
+        self.best.resize(len, UNREACHABLE);
+        // the query may start anywhere in the path
+        self.best[..stride].fill(0);
+        if trace {
+            self.directions.clear();
+            self.directions.resize(len, 0);
+        }
+
+        let ignore_case = self.case_matching.ignores_case(input);
+
+        let scoring = self.scoring;
         for row in 1..=input.len() {
+            let first_letter = if row == 1 { scoring.first_letter } else { 0 };
+            // best alignment of this row that skips the path byte at `col`
+            let mut gap = UNREACHABLE;
             for col in 1..=to_match.len() {
-                let matching = if input[row-1] == to_match[col-1] {
-                    gap_length = 0;
-                    self.scoring_matrix[(row-1) * to_match.len() + col - 1] + BONUS_RIGHT_LETTER + if row == 1 { BONUS_FIRST_LETTER } else { 0 }
+                let index = row * stride + col;
+                let diagonal = index - stride - 1;
+                let mut directions = 0;
+
+                let opening = self.matches[index - 1] - scoring.gap_open;
+                let extending = gap - scoring.gap_extend;
+                if opening >= extending {
+                    directions |= GAP_OPENED;
+                }
+                gap = opening.max(extending).max(UNREACHABLE);
+
+                // `best` is reachable whenever `matches` is, the query so far has to be matched
+                if self.best[diagonal] > UNREACHABLE && bytes_eq(input[row - 1], to_match[col - 1], ignore_case) {
+                    let after_gap = self.best[diagonal];
+                    let consecutive = self.matches[diagonal] + scoring.consecutive;
+                    let previous = if consecutive > after_gap {
+                        directions |= FROM_CONSECUTIVE;
+                        consecutive
+                    } else {
+                        after_gap
+                    };
+                    self.matches[index] = previous + scoring.match_letter + first_letter + self.bonuses[col - 1];
+                }
+
+                self.best[index] = if gap > self.matches[index] {
+                    directions |= FROM_GAP;
+                    gap
                 } else {
-                    gap_length += 1;
-                    self.scoring_matrix[(row-1) * to_match.len() + col - 1] - GAP_PENALTY
+                    self.matches[index]
                 };
+                if trace {
+                    self.directions[index] = directions;
+                }
+            }
+        }
 
-                let deleting = self.scoring_matrix[(row-1) * to_match.len() + col] - GAP_PENALTY * gap_length;
-                let inserting = self.scoring_matrix[row * to_match.len() + col- 1] - GAP_PENALTY * gap_length;
+        // nothing after the last matched byte counts against the path
+        let last_row = &self.matches[input.len() * stride..];
+        let Some((col, points)) = last_row.iter().enumerate().skip(1).max_by_key(|(col, points)| (**points, std::cmp::Reverse(*col))) else {
+            return (0, 0);
+        };
+        if *points <= UNREACHABLE {
+            return (0, 0);
+        }
+        // a match always scores, however many gaps it has
+        ((*points).max(1), col)
+    }
 
-                self.scoring_matrix[row * to_match.len() + col] = *[0, matching, deleting, inserting].iter().max().unwrap();
-                max = max.max(self.scoring_matrix[row * to_match.len() + col]);
+    /// Matches at the start of a path segment, after a separator like `_`, at a
+    /// camelCase hump or inside the file name are worth more than the rest.
+    fn fill_bonuses(&mut self, to_match: &[u8]) {
+        let basename_start = to_match.iter().rposition(|ch| *ch == b'/').map_or(0, |i| i + 1);
+        let scoring = self.scoring;
+        self.bonuses.clear();
+        for (i, &ch) in to_match.iter().enumerate() {
+            let previous = if i == 0 { b'/' } else { to_match[i - 1] };
+            let mut bonus = match previous {
+                b'/' => scoring.segment_start,
+                b'_' | b'-' | b'.' | b' ' => scoring.boundary,
+                _ if previous.is_ascii_lowercase() && ch.is_ascii_uppercase() => scoring.camel_case,
+                _ => 0,
+            };
+            if i >= basename_start {
+                bonus += scoring.basename;
             }
+            self.bonuses.push(bonus);
         }
-
-        max
     }
 
     fn instant_match(&self, input: &[u8], to_match: FilePath) -> Option<i32> {
+        self.instant_match_start(input, to_match).map(|_| i32::MAX)
+    }
+
+    /// Start of the path segment that equals `input`.
+    fn instant_match_start(&self, input: &[u8], to_match: FilePath) -> Option<usize> {
+        let ignore_case = self.case_matching.ignores_case(input);
         // for part in to_match.into_iter().filter(|part| part.len() == input.len()) {
         for part in to_match.into_iter() {
-            if part == input {
-                return Some(i32::MAX);
+            if part == input || (ignore_case && part.eq_ignore_ascii_case(input)) {
+                return Some(part.as_ptr() as usize - to_match.data.as_ptr() as usize);
             }
         }
         None
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_positions() {
+        let mut matcher = FuzzyMatcher::new(CaseMatching::Smart, ScoringConfig::default());
+
+        let path = FilePath::new(b"./src/main.rs");
+        assert_eq!(matcher.match_positions(b"src", path), vec![2, 3, 4]);
+        assert_eq!(matcher.match_positions(b"main", path), vec![6, 7, 8, 9]);
+        assert!(matcher.match_positions(b"xyz", path).is_empty());
+    }
+
+    #[test]
+    fn test_smart_case() {
+        let mut matcher = FuzzyMatcher::new(CaseMatching::Smart, ScoringConfig::default());
+        let path = FilePath::new(b"./README.md");
+        assert_eq!(matcher.smith_waterman(b"readme.md", path), i32::MAX);
+        assert_eq!(matcher.smith_waterman(b"README.md", path), i32::MAX);
+        assert_ne!(matcher.smith_waterman(b"Readme.md", path), i32::MAX);
+
+        let mut matcher = FuzzyMatcher::new(CaseMatching::Respect, ScoringConfig::default());
+        assert_ne!(matcher.smith_waterman(b"readme.md", path), i32::MAX);
+
+        let mut matcher = FuzzyMatcher::new(CaseMatching::Ignore, ScoringConfig::default());
+        assert_eq!(matcher.smith_waterman(b"Readme.md", path), i32::MAX);
+    }
+
+    // (query, paths from the best to the worst match), every path has to score
+    // strictly less than the one before it
+    const RANKING: &[(&str, &[&str])] = &[
+        ("fmrs", &["./src/fuzzy_match.rs", "./a/deep/formers/directory/tree.txt"]),
+        ("match", &["./src/fuzzy_match.rs", "./src/rematching/mod.rs"]),
+        ("bar", &["./foo/fooBar.rs", "./foo/foobar.rs"]),
+        ("walk", &["./src/walker.rs", "./walkers/src/lib.rs"]),
+        // consecutive bytes beat scattered ones
+        ("arena", &["./src/arena.rs", "./src/a_r_e_n_a.rs", "./src/abcdefghijklmnopqrstuvwxyz/renamed/anything.rs"]),
+        // the file name beats a directory of the same name
+        ("quer", &["./src/query.rs", "./query/mod.rs"]),
+        // fewer skipped bytes in the middle
+        ("mrs", &["./src/main.rs", "./src/mainloop.rs", "./src/mainloop_helpers.rs"]),
+        ("cargo", &["./docs/cargo_guide.md", "./ci/scargo.txt"]),
+        ("gi", &["./.gitignore", "./src/glob_filter.rs"]),
+    ];
+
+    // (query, path) pairs that must not match at all
+    const NO_MATCH: &[(&str, &str)] = &[
+        ("zzz", "./src/main.rs"),
+        ("mian", "./src/main.rs"),
+        ("srcc", "./src/main.rs"),
+        ("rs.", "./src/main.rs"),
+        ("Main", "./src/main.rs"),
+    ];
+
+    #[test]
+    fn test_ranking() {
+        let mut matcher = FuzzyMatcher::new(CaseMatching::Smart, ScoringConfig::default());
+        for (query, paths) in RANKING {
+            let scores = paths.iter().map(|path| matcher.smith_waterman(query.as_bytes(), FilePath::new(path.as_bytes()))).collect::<Vec<_>>();
+            assert!(scores[scores.len() - 1] > 0, "{:?} doesn't match {:?}", query, paths[paths.len() - 1]);
+            for (i, pair) in scores.windows(2).enumerate() {
+                assert!(pair[0] > pair[1], "{:?}: {:?} ({}) should beat {:?} ({})", query, paths[i], pair[0], paths[i + 1], pair[1]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_match() {
+        let mut matcher = FuzzyMatcher::new(CaseMatching::Smart, ScoringConfig::default());
+        for (query, path) in NO_MATCH {
+            let path = FilePath::new(path.as_bytes());
+            assert_eq!(matcher.smith_waterman(query.as_bytes(), path), 0, "{:?} matches {:?}", query, path.path());
+            assert!(matcher.match_positions(query.as_bytes(), path).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_affine_gaps() {
+        let mut scoring = ScoringConfig::default();
+        scoring.apply("consecutive=0, segment-start=0, boundary=0, camel-case=0, basename=0").unwrap();
+        let mut matcher = FuzzyMatcher::new(CaseMatching::Smart, scoring);
+        let mut score = |path: &'static str| matcher.smith_waterman(b"abc", FilePath::new(path.as_bytes()));
+
+        // one long gap costs less than two short ones skipping as many bytes
+        assert_eq!(score("axxxxbc"), 3 * 10 + 20 - (3 + 3));
+        assert_eq!(score("axxbxxc"), 3 * 10 + 20 - (3 + 1) * 2);
+
+        assert!(scoring.apply("gap-open").is_err());
+        assert!(scoring.apply("gap=1").is_err());
+        assert!(scoring.apply("gap-open=x").is_err());
+    }
+
+    #[test]
+    fn test_weight_range() {
+        let mut scoring = ScoringConfig::default();
+        assert!(scoring.apply("gap-open=2000000000").is_err());
+        assert!(scoring.apply("consecutive=-1").is_err());
+        assert!(scoring.apply("basename=10001").is_err());
+        assert_eq!(scoring, ScoringConfig::default());
+        scoring.apply("match=0,basename=10000").unwrap();
+        assert_eq!((scoring.match_letter, scoring.basename), (0, 10_000));
+    }
+
+    #[test]
+    fn test_scattered_positions() {
+        let mut matcher = FuzzyMatcher::new(CaseMatching::Smart, ScoringConfig::default());
+        let path = FilePath::new(b"./src/fuzzy_match.rs");
+        assert_eq!(matcher.match_positions(b"fmrs", path), vec![6, 12, 18, 19]);
+        assert_eq!(matcher.match_positions(b"sfz", path), vec![2, 6, 8]);
+    }
+}
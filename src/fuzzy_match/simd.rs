@@ -0,0 +1,183 @@
+//! Vectorized `FuzzyMatcher::score` without a trace. Cells on the same
+//! anti-diagonal of the matrix don't depend on each other, so the kernel walks
+//! the diagonals with one row of the matrix, one byte of the query, per lane.
+
+use super::ScoringConfig;
+
+const LANES: usize = 8;
+
+/// Same result as `FuzzyMatcher::scalar_score`, `None` when the CPU or the
+/// query doesn't fit the kernel and the scalar version has to run instead.
+pub(super) fn score(input: &[u8], to_match: &[u8], bonuses: &[i32], scoring: &ScoringConfig, ignore_case: bool, buffer: &mut Vec<i32>) -> Option<(i32, usize)> {
+    if input.is_empty() || input.len() > LANES || to_match.is_empty() {
+        return None;
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("avx2") {
+        // safe, the CPU was just checked for avx2
+        return Some(unsafe { avx2::score(input, to_match, bonuses, scoring, ignore_case, buffer) });
+    }
+
+    None
+}
+
+fn fold(byte: u8, ignore_case: bool) -> i32 {
+    if ignore_case { byte.to_ascii_lowercase() as i32 } else { byte as i32 }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    use super::{fold, LANES};
+    use crate::fuzzy_match::{ScoringConfig, UNREACHABLE};
+
+    /// Lane `i` of the result is lane `i - 1` of `v`, lane 0 is `first`.
+    #[target_feature(enable = "avx2")]
+    fn shift_rows(v: __m256i, first: i32) -> __m256i {
+        let shifted = _mm256_permutevar8x32_epi32(v, _mm256_setr_epi32(0, 0, 1, 2, 3, 4, 5, 6));
+        _mm256_blend_epi32::<1>(shifted, _mm256_set1_epi32(first))
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn load(values: &[i32], offset: usize) -> __m256i {
+        debug_assert!(offset + LANES <= values.len());
+        _mm256_loadu_si256(values.as_ptr().add(offset) as *const __m256i)
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn score(input: &[u8], to_match: &[u8], bonuses: &[i32], scoring: &ScoringConfig, ignore_case: bool, buffer: &mut Vec<i32>) -> (i32, usize) {
+        let rows = input.len();
+        let cols = to_match.len();
+
+        // the path reversed and padded on both sides, so the bytes of a whole
+        // diagonal are one unaligned load, with every lane a row further down
+        // and so a column further left
+        let len = cols + 2 * LANES;
+        buffer.clear();
+        buffer.resize(3 * len, 0);
+        let (bytes, rest) = buffer.split_at_mut(len);
+        let (position_bonuses, valid) = rest.split_at_mut(len);
+        // never equal to a query byte, not even the padding of the query
+        bytes.fill(-2);
+        for (col, byte) in to_match.iter().enumerate() {
+            let at = LANES + cols - 1 - col;
+            bytes[at] = fold(*byte, ignore_case);
+            position_bonuses[at] = bonuses[col];
+            valid[at] = -1;
+        }
+
+        let mut query = [-1; LANES];
+        for (lane, byte) in input.iter().enumerate() {
+            query[lane] = fold(*byte, ignore_case);
+        }
+        let mut letter = [scoring.match_letter; LANES];
+        letter[0] += scoring.first_letter;
+
+        let query = _mm256_loadu_si256(query.as_ptr() as *const __m256i);
+        let letter = _mm256_loadu_si256(letter.as_ptr() as *const __m256i);
+        let consecutive = _mm256_set1_epi32(scoring.consecutive);
+        let gap_open = _mm256_set1_epi32(scoring.gap_open);
+        let gap_extend = _mm256_set1_epi32(scoring.gap_extend);
+        let unreachable = _mm256_set1_epi32(UNREACHABLE);
+
+        // the previous diagonal and the one before it, nothing is reachable
+        // left of the path
+        let (mut matches1, mut best1, mut gap1) = (unreachable, unreachable, unreachable);
+        let (mut matches2, mut best2) = (unreachable, unreachable);
+        let mut max = UNREACHABLE;
+        let mut max_col = 0;
+        let mut last_row = [0; LANES];
+
+        // cell (row, col) is on diagonal row + col, lane row - 1
+        for diagonal in 2..=rows + cols {
+            let offset = LANES + cols + 1 - diagonal;
+            let path = load(bytes, offset);
+            let bonus = load(position_bonuses, offset);
+            let inside = load(valid, offset);
+
+            // the cell up left, row 0 can start a match anywhere
+            let diagonal_best = shift_rows(best2, 0);
+            let diagonal_matches = shift_rows(matches2, UNREACHABLE);
+            let is_match = _mm256_and_si256(_mm256_cmpeq_epi32(query, path), _mm256_cmpgt_epi32(diagonal_best, unreachable));
+            let previous = _mm256_max_epi32(_mm256_add_epi32(diagonal_matches, consecutive), diagonal_best);
+            let points = _mm256_add_epi32(_mm256_add_epi32(previous, letter), bonus);
+            let matches = _mm256_blendv_epi8(unreachable, points, is_match);
+
+            // the cell to the left
+            let opening = _mm256_sub_epi32(matches1, gap_open);
+            let extending = _mm256_sub_epi32(gap1, gap_extend);
+            let gap = _mm256_max_epi32(_mm256_max_epi32(opening, extending), unreachable);
+            let best = _mm256_max_epi32(gap, matches);
+
+            let matches = _mm256_blendv_epi8(unreachable, matches, inside);
+            let gap = _mm256_blendv_epi8(unreachable, gap, inside);
+            let best = _mm256_blendv_epi8(unreachable, best, inside);
+
+            if diagonal > rows {
+                _mm256_storeu_si256(last_row.as_mut_ptr() as *mut __m256i, matches);
+                // the first column wins a tie like in the scalar version
+                if last_row[rows - 1] > max {
+                    max = last_row[rows - 1];
+                    max_col = diagonal - rows;
+                }
+            }
+
+            (matches2, best2) = (matches1, best1);
+            (matches1, best1, gap1) = (matches, best, gap);
+        }
+
+        if max <= UNREACHABLE {
+            return (0, 0);
+        }
+        (max.max(1), max_col)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fuzzy_match::{CaseMatching, FuzzyMatcher}, test_util::XorShift};
+
+    #[test]
+    fn test_same_as_scalar() {
+        let mut buffer = Vec::new();
+        if score(b"a", b"a", &[0], &ScoringConfig::default(), false, &mut buffer).is_none() {
+            eprintln!("skipped test_same_as_scalar, the CPU can't run the kernel");
+            return;
+        }
+
+        let mut rng = XorShift::new(0x9e37_79b9_7f4a_7c15);
+        let mut next = |n: usize| rng.below(n);
+        // few different bytes so most queries match, some of them more than once
+        let alphabet = b"abcAB/_.-";
+
+        for case_matching in [CaseMatching::Smart, CaseMatching::Respect, CaseMatching::Ignore] {
+            let mut scoring = ScoringConfig::default();
+            if case_matching == CaseMatching::Ignore {
+                scoring.apply("gap-open=7,gap-extend=0,consecutive=0,first-letter=0").unwrap();
+            }
+            let mut matcher = FuzzyMatcher::new(case_matching, scoring);
+            for _ in 0..20_000 {
+                let input = (0..1 + next(LANES)).map(|_| alphabet[next(alphabet.len())]).collect::<Vec<_>>();
+                let path = (0..1 + next(80)).map(|_| alphabet[next(alphabet.len())]).collect::<Vec<_>>();
+                let ignore_case = case_matching.ignores_case(&input);
+
+                matcher.fill_bonuses(&path);
+                let vectorized = score(&input, &path, &matcher.bonuses, &scoring, ignore_case, &mut buffer).unwrap();
+                let scalar = matcher.scalar_score(&input, &path, false);
+                assert_eq!(vectorized, scalar, "{:?} in {:?}", String::from_utf8_lossy(&input), String::from_utf8_lossy(&path));
+            }
+        }
+    }
+
+    #[test]
+    fn test_falls_back() {
+        let mut buffer = Vec::new();
+        let scoring = ScoringConfig::default();
+        assert_eq!(score(b"abcdefghi", b"./abcdefghi", &[0; 11], &scoring, true, &mut buffer), None);
+        assert_eq!(score(b"", b"./a", &[0; 3], &scoring, true, &mut buffer), None);
+    }
+}
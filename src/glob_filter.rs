@@ -0,0 +1,73 @@
+use std::{ffi::OsStr, path::Path};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+
+/// A set of `--exclude`/`--include` patterns. Patterns without a `/` are
+/// matched against the entry's file name, the rest against the entry's path
+/// relative to the walk root, so both `target` and `**/target` work.
+pub struct GlobFilter {
+    names: GlobSet,
+    paths: GlobSet,
+    len: usize,
+}
+
+impl GlobFilter {
+    pub fn new(patterns: &[String]) -> Result<Self, globset::Error> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in patterns {
+            let pattern = pattern.trim_end_matches('/');
+            let glob = GlobBuilder::new(pattern).literal_separator(true).build()?;
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+
+        Ok(Self {
+            names: names.build()?,
+            paths: paths.build()?,
+            len: patterns.len(),
+        })
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            names: GlobSet::empty(),
+            paths: GlobSet::empty(),
+            len: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_match(&self, relative_path: &Path, name: &OsStr) -> bool {
+        self.names.is_match(name) || self.paths.is_match(relative_path)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_and_path_patterns() {
+        let filter = GlobFilter::new(&["target".into(), "src/**/*.rs".into()]).unwrap();
+        let matches = |path: &str| {
+            let path = Path::new(path);
+            filter.is_match(path, path.file_name().unwrap())
+        };
+
+        assert!(matches("target"));
+        assert!(matches("crates/foo/target"));
+        assert!(matches("src/main.rs"));
+        assert!(matches("src/a/b/lib.rs"));
+        assert!(!matches("tests/main.rs"));
+        assert!(!matches("targets"));
+    }
+}
@@ -0,0 +1,157 @@
+use std::{path::{Path, PathBuf}, sync::Arc};
+
+use ignore::{gitignore::{Gitignore, GitignoreBuilder}, Match};
+
+
+// checked in this order inside a single directory, first match wins
+const IGNORE_FILES: [&str; 2] = [".ignore", ".gitignore"];
+
+/// Ignore rules that apply to one directory of the walk. Every directory that
+/// has ignore files of its own gets a new node linked to its parent, deeper
+/// rules are checked first so nested files and `!negations` override the
+/// ones above them.
+pub struct IgnoreRules {
+    matchers: Vec<Gitignore>,
+    parent: Option<Arc<IgnoreRules>>,
+    base: Arc<BaseRules>,
+}
+
+/// Rules that come from outside of the walked tree: ignore files in the
+/// ancestors of the walk root and git's global `core.excludesFile`.
+struct BaseRules {
+    walk_root: PathBuf,
+    abs_root: PathBuf,
+    ancestors: Vec<Gitignore>,
+    global: Gitignore,
+}
+
+impl IgnoreRules {
+    /// Rules for the parents of `root`. Use `child` with `root` itself to load
+    /// the ignore files of the walk root.
+    pub fn new(root: &Path) -> Arc<Self> {
+        let abs_root = std::path::absolute(root).unwrap_or_else(|_| root.to_owned());
+        let mut ancestors = Vec::new();
+        for dir in abs_root.ancestors().skip(1) {
+            ancestors.extend(load_dir(dir));
+        }
+
+        let base = BaseRules {
+            walk_root: root.to_owned(),
+            abs_root,
+            ancestors,
+            global: Gitignore::global().0,
+        };
+
+        Arc::new(Self {
+            matchers: Vec::new(),
+            parent: None,
+            base: Arc::new(base),
+        })
+    }
+
+    /// Rules for `dir`, where `self` holds the rules of its parent. Returns
+    /// `self` when `dir` has no ignore files so the chain stays short.
+    pub fn child(self: &Arc<Self>, dir: &Path) -> Arc<Self> {
+        let matchers = load_dir(dir);
+        if matchers.is_empty() {
+            return self.clone();
+        }
+
+        Arc::new(Self {
+            matchers,
+            parent: Some(self.clone()),
+            base: self.base.clone(),
+        })
+    }
+
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        // the repository's own files are never what you're looking for, rg and fd skip them too
+        if path.file_name().is_some_and(|name| name == ".git") {
+            return true;
+        }
+
+        let mut rules = Some(self);
+        while let Some(current) = rules {
+            for matcher in &current.matchers {
+                match matcher.matched(path, is_dir) {
+                    Match::None => (),
+                    m => return m.is_ignore(),
+                }
+            }
+            rules = current.parent.as_deref();
+        }
+
+        let base = &self.base;
+        if !base.ancestors.is_empty() {
+            // ancestor rules are rooted at absolute paths
+            let relative = path.strip_prefix(&base.walk_root).unwrap_or(path);
+            let abs_path = base.abs_root.join(relative);
+            for matcher in &base.ancestors {
+                match matcher.matched(&abs_path, is_dir) {
+                    Match::None => (),
+                    m => return m.is_ignore(),
+                }
+            }
+        }
+
+        base.global.matched(path, is_dir).is_ignore()
+    }
+}
+
+fn load_dir(dir: &Path) -> Vec<Gitignore> {
+    let mut matchers = Vec::new();
+    for name in IGNORE_FILES {
+        let path = dir.join(name);
+        if !path.is_file() {
+            continue;
+        }
+        let (gitignore, _) = Gitignore::new(path);
+        if !gitignore.is_empty() {
+            matchers.push(gitignore);
+        }
+    }
+
+    let exclude = dir.join(".git/info/exclude");
+    if exclude.is_file() {
+        let mut builder = GitignoreBuilder::new(dir);
+        builder.add(exclude);
+        if let Ok(gitignore) = builder.build() {
+            if !gitignore.is_empty() {
+                matchers.push(gitignore);
+            }
+        }
+    }
+
+    matchers
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn test_nested_rules() {
+        let root = std::env::temp_dir().join(format!("boo_ignore_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub/target")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(root.join("sub/.gitignore"), "!keep.log\n").unwrap();
+
+        let rules = IgnoreRules::new(&root).child(&root);
+        assert!(rules.is_ignored(&root.join("a.log"), false));
+        assert!(!rules.is_ignored(&root.join("a.rs"), false));
+        assert!(rules.is_ignored(&root.join(".git"), true));
+        assert!(!rules.is_ignored(&root.join(".github"), true));
+
+        let sub = rules.child(&root.join("sub"));
+        assert!(sub.is_ignored(&root.join("sub/target"), true));
+        assert!(!sub.is_ignored(&root.join("sub/target"), false));
+        assert!(sub.is_ignored(&root.join("sub/other.log"), false));
+        assert!(!sub.is_ignored(&root.join("sub/keep.log"), false));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use std::{io::{self, BufRead, BufReader, Read, Write}, process::{Child, Command, Stdio}, sync::{atomic::Ordering, Arc}, thread::{self, available_parallelism, JoinHandle}, time::{Duration, Instant}};
+
+use crate::{arena::Arena, byte_storage::FilePath, WALKER_THREADS};
+
+// lines are handed to the storages in batches, or sooner if the producer is slow
+const BATCH_SIZE: usize = 1000;
+const FLUSH_INTERVAL: Duration = Duration::from_millis(50);
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Fills the same storages as `walker::Walker`, but with delimited lines read
+/// from stdin or a child process instead of walking the file system.
+pub struct LineSource {
+    pub refs: Vec<Arc<parking_lot::RwLock<Arena<FilePath>>>>,
+    handle: JoinHandle<Arena<u8>>,
+    /// the source command, killed when boo is done with it
+    child: Option<Child>,
+    /// what the source command printed on stderr while the UI was up
+    captured_stderr: Option<JoinHandle<Vec<u8>>>,
+}
+
+impl LineSource {
+    pub fn run(reader: impl Read + Send + 'static, delimiter: u8) -> Self {
+        let storages = available_parallelism().unwrap().get();
+        let refs = (0..storages)
+            .map(|_| Arc::new(parking_lot::RwLock::new(Arena::new())))
+            .collect::<Vec<_>>();
+
+        // one reader counts as one walker thread so "still loading" works the same
+        WALKER_THREADS.store(1, Ordering::Relaxed);
+        let handle = spawn(reader, delimiter, refs.clone());
+
+        Self {
+            refs,
+            handle,
+            child: None,
+            captured_stderr: None,
+        }
+    }
+
+    /// Runs `command` with `sh -c` and reads its stdout. With `capture_stderr`
+    /// its stderr is kept until `wait` instead of ending up on top of the UI.
+    pub fn command(command: &str, delimiter: u8, capture_stderr: bool) -> io::Result<Self> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(if capture_stderr { Stdio::piped() } else { Stdio::inherit() })
+            .spawn()?;
+
+        let stdout = child.stdout.take().unwrap();
+        let captured_stderr = child.stderr.take().map(|mut stderr| {
+            thread::spawn(move || {
+                let mut captured = Vec::new();
+                let _ = stderr.read_to_end(&mut captured);
+                captured
+            })
+        });
+        let mut source = Self::run(stdout, delimiter);
+        source.child = Some(child);
+        source.captured_stderr = captured_stderr;
+
+        Ok(source)
+    }
+
+    /// Waits for the source command to exit and replays its captured stderr,
+    /// an error if it failed. A command that is still being read from is
+    /// killed instead, boo is done with it.
+    pub fn wait(&mut self) -> io::Result<()> {
+        let Some(mut child) = self.child.take() else {
+            return Ok(());
+        };
+        if WALKER_THREADS.load(Ordering::Acquire) > 0 {
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(());
+        }
+
+        let status = child.wait()?;
+        if let Some(captured) = self.captured_stderr.take() {
+            io::stderr().write_all(&captured.join().unwrap_or_default())?;
+        }
+        if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("source command failed: {}", status)))
+        }
+    }
+}
+
+impl Drop for LineSource {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn spawn(reader: impl Read + Send + 'static, delimiter: u8, refs: Vec<Arc<parking_lot::RwLock<Arena<FilePath>>>>) -> JoinHandle<Arena<u8>> {
+    thread::spawn(move || {
+        let mut arena = Arena::new();
+        let mut reader = BufReader::with_capacity(READ_BUFFER_SIZE, reader);
+        let mut line = Vec::new();
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        let mut next_storage = 0;
+        let mut index = 0;
+        let mut last_flush = Instant::now();
+
+        loop {
+            // without a whole line buffered the next read can block for as long
+            // as the producer likes, whatever was read so far has to show up now
+            if !batch.is_empty() && !reader.buffer().contains(&delimiter) {
+                flush(&refs, &mut batch, &mut next_storage);
+                last_flush = Instant::now();
+            }
+
+            line.clear();
+            let read = reader.read_until(delimiter, &mut line).unwrap_or(0);
+            if read == 0 {
+                break;
+            }
+
+            let mut end = line.len();
+            if line[end - 1] == delimiter {
+                end -= 1;
+            }
+            if delimiter == b'\n' && end > 0 && line[end - 1] == b'\r' {
+                end -= 1;
+            }
+            // paths are shown as str, skip anything that isn't utf-8
+            if end > 0 && std::str::from_utf8(&line[..end]).is_ok() {
+                let mut item = FilePath::new(arena.extend_and_get(&line[..end]));
+                item.index = index;
+                index += 1;
+                batch.push(item);
+            }
+
+            if batch.len() >= BATCH_SIZE || (!batch.is_empty() && last_flush.elapsed() > FLUSH_INTERVAL) {
+                flush(&refs, &mut batch, &mut next_storage);
+                last_flush = Instant::now();
+            }
+        }
+
+        if !batch.is_empty() {
+            flush(&refs, &mut batch, &mut next_storage);
+        }
+        WALKER_THREADS.fetch_sub(1, Ordering::Release);
+
+        arena
+    })
+}
+
+/// Hands `batch` to the next storage in turn.
+fn flush(refs: &[Arc<parking_lot::RwLock<Arena<FilePath>>>], batch: &mut Vec<FilePath>, next_storage: &mut usize) {
+    refs[*next_storage].write().extend(batch);
+    batch.clear();
+    *next_storage = (*next_storage + 1) % refs.len();
+}
@@ -1,12 +1,15 @@
 #![allow(unused_variables, dead_code)]
 
-use std::{env, io::{self, stderr}, sync::{atomic::{AtomicBool, AtomicU32, Ordering}, Arc, Mutex}, thread::{self, available_parallelism, JoinHandle}, time::Duration};
+use std::{env, io::{self, stderr, IsTerminal}, process::ExitCode, sync::{atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering}, Arc, Mutex}, thread::{self, available_parallelism, JoinHandle}, time::Duration};
 use std::io::Write;
 
 use arena::Arena;
-use byte_storage::FilePath;
+use byte_storage::{type_filter_bit, type_filter_name, FilePath, TYPE_DIR, TYPE_EXECUTABLE, TYPE_FILE, TYPE_SYMLINK};
 use crossbeam::channel::{unbounded, Receiver, Sender};
-use fuzzy_match::FuzzyMatcher;
+use fuzzy_match::{CaseMatching, FuzzyMatcher, ScoringConfig};
+use glob_filter::GlobFilter;
+use query::Query;
+use tiebreak::Tiebreak;
 
 use crossterm::{cursor, event, execute, queue, style::Print, terminal::{self, Clear, EnterAlternateScreen, LeaveAlternateScreen}, ExecutableCommand, QueueableCommand};
 use crossterm::style::Stylize;
@@ -15,26 +18,97 @@ mod fuzzy_match;
 mod byte_storage;
 mod walker;
 mod arena;
-
+mod ignore_rules;
+mod glob_filter;
+mod line_source;
+mod query;
+mod tiebreak;
+#[cfg(test)]
+mod test_util;
+
+
+// exit codes, cancelling never prints anything to stdout
+const EXIT_SELECTED: u8 = 0;
+const EXIT_NO_MATCH: u8 = 1;
+const EXIT_ERROR: u8 = 2;
+const EXIT_CANCELLED: u8 = 130;
+
+// query and status line above the results
+const HEADER_ROWS: u16 = 2;
+// how many screens of results are kept around for scrolling
+const RESULT_PAGES: usize = 10;
 
 static WALKER_THREADS: AtomicU32 = AtomicU32::new(0);
 static SHOULD_QUIT: AtomicBool = AtomicBool::new(false);
 static SEARCH_WORKERS: AtomicU32 = AtomicU32::new(0);
+static SHOW_HIDDEN: AtomicBool = AtomicBool::new(false);
+static TYPE_FILTER: AtomicU8 = AtomicU8::new(0);
+
+/// The runtime toggles an item has to pass before it's scored.
+#[derive(Copy, Clone, PartialEq)]
+struct ItemFilter {
+    show_hidden: bool,
+    type_filter: u8,
+}
+
+impl ItemFilter {
+    fn current() -> Self {
+        Self {
+            show_hidden: SHOW_HIDDEN.load(Ordering::Relaxed),
+            type_filter: TYPE_FILTER.load(Ordering::Relaxed),
+        }
+    }
+
+    fn matches(&self, item: &FilePath) -> bool {
+        (self.show_hidden || !item.hidden) && item.kind.matches(self.type_filter)
+    }
+}
+
+/// Scores `items` against `query`, best first, leaving out anything scoring less than `min`.
+/// The positions of everything that matched, whatever its score, are added to `matched`.
+fn score_items<'a>(matcher: &mut FuzzyMatcher, query: &Query, items: &'a [FilePath], filter: ItemFilter, tiebreak: &[Tiebreak], min: i32, mut matched: Option<&mut Vec<usize>>) -> (Vec<i32>, Vec<&'a FilePath>) {
+    let mut points = items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| filter.matches(item))
+        // a query of only negations matches with 0, still counts as a match
+        .filter_map(|(i, item)| query.score(matcher, *item).map(|point| (i, point.max(1), item)))
+        .inspect(|(i, _, _)| matched.iter_mut().for_each(|matched| matched.push(*i)))
+        .map(|(_, point, item)| (point, item))
+        // an item scoring exactly `min` can still win the tiebreak against the last result
+        .filter(|(point, _)| *point >= min)
+        .collect::<Vec<_>>();
+
+    points.sort_unstable_by(|(p1, i1), (p2, i2)| p2.cmp(p1).then_with(|| tiebreak::compare(tiebreak, i1, i2)));
+    points.into_iter().unzip()
+}
+
+/// What a worker remembers of its last finished scan.
+struct Narrowing {
+    query: Arc<Query>,
+    filter: ItemFilter,
+    /// indices of the items that matched `query`, ascending
+    candidates: Vec<u32>,
+    /// how many items the storage had, later ones were never looked at
+    scanned: usize,
+}
 
 struct SearchWorker {
     thread_id: usize,
-    receiver: Receiver<String>,
+    receiver: Receiver<Arc<Query>>,
     collector: Arc<Mutex<Collector>>,
     data: Arc<parking_lot::RwLock<Arena<FilePath>>>,
     tag: i32,
     current_min: i32,
     walking_is_done: bool,
     matcher: FuzzyMatcher,
-    input: Option<String>,
+    tiebreak: Vec<Tiebreak>,
+    input: Option<Arc<Query>>,
+    narrowing: Option<Narrowing>,
 }
 
 impl SearchWorker {
-    fn new(thread_id: usize, collector: Arc<Mutex<Collector>>, receiver: Receiver<String>, data: Arc<parking_lot::RwLock<Arena<FilePath>>>) -> Self { 
+    fn new(thread_id: usize, collector: Arc<Mutex<Collector>>, receiver: Receiver<Arc<Query>>, data: Arc<parking_lot::RwLock<Arena<FilePath>>>, case_matching: CaseMatching, scoring: ScoringConfig, tiebreak: Vec<Tiebreak>) -> Self { 
         Self { 
             thread_id,
             receiver,
@@ -43,8 +117,10 @@ impl SearchWorker {
             tag: 0,
             current_min: 0,
             walking_is_done: false,
-            matcher: FuzzyMatcher::new(),
+            matcher: FuzzyMatcher::new(case_matching, scoring),
+            tiebreak,
             input: None,
+            narrowing: None,
         } 
     }
 
@@ -52,39 +128,63 @@ impl SearchWorker {
         'run_loop: while !SHOULD_QUIT.load(Ordering::Relaxed) {
             if let Some(input) = self.input.clone() {
                 self.current_min = 0;
+                let filter = ItemFilter::current();
                 let data = self.data.read();
                 let slices = data.read_only_view();
                 drop(data);
+                let total = slices.iter().map(|slice| slice.len()).sum::<usize>();
+
+                // when the query only got more specific, whatever didn't match
+                // the last one can't match this one either
+                let previous = self.narrowing.take().filter(|previous| previous.filter == filter && input.narrows(&previous.query));
+                let mut indices: Box<dyn Iterator<Item = usize>> = match &previous {
+                    Some(previous) => Box::new(previous.candidates.iter().map(|i| *i as usize).chain(previous.scanned..total)),
+                    None => Box::new(0..total),
+                };
+
+                let mut candidates = Vec::new();
+                let mut chunk_indices = Vec::with_capacity(50);
+                let mut chunk = Vec::with_capacity(50);
+                let mut matched = Vec::with_capacity(50);
+                loop {
+                    chunk_indices.clear();
+                    chunk_indices.extend(indices.by_ref().take(50));
+                    if chunk_indices.is_empty() {
+                        break;
+                    }
+                    chunk.clear();
+                    chunk.extend(chunk_indices.iter().map(|i| item_at(&slices, *i)));
 
-                for slice in slices {
-                    for chunk in slice.chunks(50) {
-                        let mut points = chunk
-                            .iter()
-                            .map(|item| (self.matcher.smith_waterman(input.as_bytes(), *item), item))
-                            .filter(|(point, _)| *point > self.current_min)
-                            .collect::<Vec<_>>();
-
-                        points.sort_unstable_by(|(p1, _), (p2, _)| p2.cmp(p1));
-                        let (points, paths): (Vec<i32>, Vec<&FilePath>) = points.iter().map(|p| *p).unzip();
+                    matched.clear();
+                    let (points, paths) = score_items(&mut self.matcher, &input, &chunk, filter, &self.tiebreak, self.current_min, Some(&mut matched));
+                    candidates.extend(matched.iter().map(|i| chunk_indices[*i] as u32));
 
-                        let mut collector = self.collector.lock().unwrap();
-                        self.current_min = collector.update(points, paths, self.tag);
-                        drop(collector);
+                    let mut collector = self.collector.lock().unwrap();
+                    self.current_min = collector.update(points, paths, self.tag);
+                    drop(collector);
 
-                        if SHOULD_QUIT.load(Ordering::Relaxed) {
-                            break 'run_loop
-                        }
-                        if self.has_new_input() {
-                            continue 'run_loop
-                        }
+                    if SHOULD_QUIT.load(Ordering::Relaxed) {
+                        break 'run_loop
+                    }
+                    if self.has_new_input() {
+                        // the next query can still narrow down the last finished one
+                        drop(indices);
+                        self.narrowing = previous;
+                        continue 'run_loop
                     }
                 }
 
+                self.narrowing = Some(Narrowing {
+                    query: input,
+                    filter,
+                    candidates,
+                    scanned: total,
+                });
                 if WALKER_THREADS.load(Ordering::Relaxed) == 0 {
                     self.walking_is_done = true;
                 }
                 self.input = None;
-            } else {
+            } else if !self.scan_tail() {
                 thread::sleep(Duration::from_millis(1));
                 self.has_new_input();
             }
@@ -93,6 +193,49 @@ impl SearchWorker {
         SEARCH_WORKERS.fetch_sub(1, Ordering::Relaxed);
     }
 
+    /// Scores the items added since the last finished scan, so the results of
+    /// the current query keep up with a source that is still loading. False
+    /// when there was nothing new to score.
+    fn scan_tail(&mut self) -> bool {
+        let Some(mut narrowing) = self.narrowing.take() else {
+            return false;
+        };
+        let filter = ItemFilter::current();
+        let data = self.data.read();
+        let slices = data.read_only_view();
+        drop(data);
+        let total = slices.iter().map(|slice| slice.len()).sum::<usize>();
+        // a changed filter waits for the search it triggers
+        if narrowing.filter != filter || narrowing.scanned >= total {
+            self.narrowing = Some(narrowing);
+            return false;
+        }
+
+        let mut chunk = Vec::with_capacity(50);
+        let mut matched = Vec::with_capacity(50);
+        while narrowing.scanned < total {
+            let start = narrowing.scanned;
+            chunk.clear();
+            chunk.extend((start..total.min(start + 50)).map(|i| item_at(&slices, i)));
+
+            matched.clear();
+            let (points, paths) = score_items(&mut self.matcher, &narrowing.query, &chunk, filter, &self.tiebreak, self.current_min, Some(&mut matched));
+            narrowing.candidates.extend(matched.iter().map(|i| (start + i) as u32));
+            narrowing.scanned += chunk.len();
+
+            let mut collector = self.collector.lock().unwrap();
+            self.current_min = collector.update(points, paths, self.tag);
+            drop(collector);
+
+            if SHOULD_QUIT.load(Ordering::Relaxed) || self.has_new_input() {
+                break;
+            }
+        }
+
+        self.narrowing = Some(narrowing);
+        true
+    }
+
     fn has_new_input(&mut self) -> bool {
         let mut ret = false;
         while let Ok(new_input) = self.receiver.try_recv() {
@@ -106,16 +249,27 @@ impl SearchWorker {
 }
 
 
+/// Item `index` of a storage, counting through its slices in order.
+fn item_at(slices: &[&'static [FilePath]], mut index: usize) -> FilePath {
+    for slice in slices {
+        if index < slice.len() {
+            return slice[index];
+        }
+        index -= slice.len();
+    }
+    panic!("item index out of bounds")
+}
+
+
 struct Searcher {
     handles: Vec<JoinHandle<()>>,
     collector: Arc<Mutex<Collector>>,
-    work_order_senders: Vec<Sender<String>>,
+    work_order_senders: Vec<Sender<Arc<Query>>>,
     storages: Vec<Arc<parking_lot::RwLock<Arena<FilePath>>>>,
-    input: String,
 }
 
 impl Searcher {
-    pub fn new(storages: Vec<Arc<parking_lot::RwLock<Arena<FilePath>>>>, collector: Arc<Mutex<Collector>>) -> Self {
+    pub fn new(storages: Vec<Arc<parking_lot::RwLock<Arena<FilePath>>>>, collector: Arc<Mutex<Collector>>, case_matching: CaseMatching, scoring: ScoringConfig, tiebreak: Vec<Tiebreak>) -> Self {
         let p_level = available_parallelism().unwrap().get();
         let mut handles = Vec::new();
         let mut work_order_senders = Vec::new();
@@ -128,6 +282,9 @@ impl Searcher {
                 collector.clone(),
                 wo_receiver,
                 storage.clone(),
+                case_matching,
+                scoring,
+                tiebreak.clone(),
             );
             let handle = thread::spawn(move || {
                 worker.run();
@@ -141,7 +298,6 @@ impl Searcher {
             work_order_senders,
             collector,
             storages,
-            input: String::new(),
         }
     }
 
@@ -150,8 +306,9 @@ impl Searcher {
         collector.clear();
         drop(collector);
 
+        let query = Arc::new(Query::parse(&input));
         for sender in self.work_order_senders.iter() {
-            sender.send(input.clone()).unwrap();
+            sender.send(query.clone()).unwrap();
         }
     }
 
@@ -169,19 +326,28 @@ struct Collector {
     capacity: usize,
     current_min: u32,
     tag: i32, 
+    /// orders equal scores, the same for every worker so merging is deterministic
+    tiebreak: Vec<Tiebreak>,
 }
 
 impl Collector {
-    fn new(capacity: usize) -> Self {
+    fn new(capacity: usize, tiebreak: Vec<Tiebreak>) -> Self {
         Self {
-            data: Vec::with_capacity(capacity),
-            points: Vec::with_capacity(capacity),
+            data: Vec::new(),
+            points: Vec::new(),
             capacity,
             current_min: 0,
             tag: 0,
+            tiebreak,
         }
     }
 
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.points.truncate(capacity);
+        self.data.truncate(capacity);
+    }
+
     fn clear(&mut self) {
         self.tag += 1;
         self.points.clear();
@@ -193,19 +359,24 @@ impl Collector {
         if tag != self.tag {
             return -1;
         }
-        let mut new_points = Vec::with_capacity(self.capacity);
-        let mut new_items = Vec::with_capacity(self.capacity);
+        let new_len = (self.points.len() + points.len()).min(self.capacity);
+        let mut new_points = Vec::with_capacity(new_len);
+        let mut new_items = Vec::with_capacity(new_len);
         let mut my_index = 0;
         let mut your_index = 0;
         let mut new_index = 0;
         while my_index < self.points.len() && your_index < points.len() && new_index < self.capacity {
-            if self.points[my_index] < points[your_index] {
+            let yours_first = match self.points[my_index].cmp(&points[your_index]) {
+                std::cmp::Ordering::Equal => tiebreak::compare(&self.tiebreak, items[your_index], &self.data[my_index]).is_lt(),
+                ordering => ordering.is_lt(),
+            };
+            if yours_first {
                 new_points.push(points[your_index]);
-                new_items.push(items[your_index].clone());
+                new_items.push(*items[your_index]);
                 your_index += 1;
             } else {
                 new_points.push(self.points[my_index]);
-                new_items.push(self.data[my_index].clone());
+                new_items.push(self.data[my_index]);
                 my_index += 1;
             }
             new_index += 1;
@@ -213,23 +384,23 @@ impl Collector {
 
         while my_index < self.points.len() && new_index < self.capacity {
             new_points.push(self.points[my_index]);
-            new_items.push(self.data[my_index].clone());
+            new_items.push(self.data[my_index]);
             my_index += 1; new_index += 1;
         }
 
         while your_index < points.len() && new_index < self.capacity {
             new_points.push(points[your_index]);
-            new_items.push(items[your_index].clone());
+            new_items.push(*items[your_index]);
             your_index += 1; new_index += 1;
         }
 
         for i in 0..new_points.len() {
             if i < self.points.len() {
                 self.points[i] = new_points[i];
-                self.data[i] = new_items[i].clone();
+                self.data[i] = new_items[i];
             } else {
                 self.points.push(new_points[i]);
-                self.data.push(new_items[i].clone());
+                self.data.push(new_items[i]);
             }
         }
 
@@ -242,40 +413,284 @@ impl Collector {
 }
 
 
-fn main() -> io::Result<()> {
+/// Where the candidates come from, has to outlive every `FilePath` it handed out.
+enum Source {
+    Walker(walker::Walker),
+    Lines(line_source::LineSource),
+}
+
+impl Source {
+    fn refs(&self) -> &[Arc<parking_lot::RwLock<Arena<FilePath>>>] {
+        match self {
+            Source::Walker(walker) => &walker.refs,
+            Source::Lines(lines) => &lines.refs,
+        }
+    }
+
+    fn roots(&self) -> &[String] {
+        match self {
+            Source::Walker(walker) => &walker.roots,
+            Source::Lines(_) => &[],
+        }
+    }
+
+    /// An error if the source command exited with a failure.
+    fn wait(&mut self) -> io::Result<()> {
+        match self {
+            Source::Walker(_) => Ok(()),
+            Source::Lines(lines) => lines.wait(),
+        }
+    }
+}
+
+
+/// Scores everything the source produced and returns the `limit` best matches,
+/// used by `--filter` which never opens the UI.
+fn filter_matches(source: &Source, input: &str, limit: usize, case_matching: CaseMatching, scoring: ScoringConfig, tiebreak: &[Tiebreak]) -> Vec<FilePath> {
+    // the walker threads only exit once nothing is left to walk
+    while WALKER_THREADS.load(Ordering::Acquire) > 0 {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    let collector = Mutex::new(Collector::new(limit, tiebreak.to_vec()));
+    let filter = ItemFilter::current();
+    // an empty query matches everything
+    let query = Query::parse(input);
+    let query = &query;
+    thread::scope(|scope| {
+        for storage in source.refs() {
+            let collector = &collector;
+            scope.spawn(move || {
+                let mut matcher = FuzzyMatcher::new(case_matching, scoring);
+                for slice in storage.read().read_only_view() {
+                    let (points, paths) = score_items(&mut matcher, query, slice, filter, tiebreak, 0, None);
+                    collector.lock().unwrap().update(points, paths, 0);
+                }
+            });
+        }
+    });
+
+    collector.into_inner().unwrap().data
+}
+
+
+/// Leaves the alternate screen and raw mode when dropped, so the terminal is
+/// restored even when the UI bails out with an error.
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let mut stderr = stderr();
+        let _ = stderr.execute(Clear(terminal::ClearType::All));
+        let _ = execute!(stderr, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+
+/// What gets printed to stdout for a picked item.
+fn selection_output(item: &FilePath, cd_path: bool) -> String {
+    if cd_path {
+        item.containing_folder()
+    } else {
+        item.path().to_owned()
+    }
+}
+
+
+/// Styles `text`, which starts at byte `offset` of its path, with the
+/// characters at the matched `positions` highlighted.
+fn highlight(text: &str, offset: usize, positions: &[usize], selected: bool) -> String {
+    let mut highlighted = String::with_capacity(text.len());
+    for (i, ch) in text.char_indices() {
+        let mut styled = ch.stylize();
+        if positions.binary_search(&(offset + i)).is_ok() {
+            styled = styled.yellow();
+        } else if selected {
+            styled = styled.white();
+        }
+        if selected {
+            styled = styled.on_black();
+        }
+        highlighted.push_str(&styled.to_string());
+    }
+    highlighted
+}
+
+
+fn list_rows(rows: u16) -> usize {
+    rows.saturating_sub(HEADER_ROWS).max(1) as usize
+}
+
+
+fn print_selection(items: &[FilePath], cd_path: bool, print0: bool) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    let separator = if print0 { '\0' } else { '\n' };
+    for item in items {
+        write!(stdout, "{}{}", selection_output(item, cd_path), separator)?;
+    }
+    stdout.flush()
+}
+
+
+fn arg_value(args: &mut impl Iterator<Item = String>, name: &str) -> io::Result<String> {
+    args.next().ok_or_else(|| invalid_input(format!("{} expects a value", name)))
+}
+
+fn invalid_input(err: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, err.to_string())
+}
+
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(code) => ExitCode::from(code),
+        Err(err) => {
+            eprintln!("boo: {}", err);
+            ExitCode::from(EXIT_ERROR)
+        }
+    }
+}
+
+fn run() -> io::Result<u8> {
     // let loc = env::args().nth(1).unwrap_or(".".to_owned());
     let mut index_all = false;
     let mut cd_path = false;
-    let mut loc = ".".to_owned();
-    for arg in env::args().skip(1) {
+    let mut relative = false;
+    let mut delimiter = b'\n';
+    let mut source_cmd = None;
+    let mut filter_query = None;
+    let mut query = String::new();
+    let mut select_1 = false;
+    let mut exit_0 = false;
+    let mut print0 = false;
+    let mut case_matching = CaseMatching::Smart;
+    let mut limit = None;
+    let mut tiebreak = tiebreak::DEFAULT_TIEBREAK.to_vec();
+    let mut scoring = ScoringConfig::default();
+    // weights shared by a team can live in the environment, --scoring still wins
+    if let Ok(list) = env::var("BOO_SCORING") {
+        scoring.apply(&list).map_err(|err| invalid_input(format!("BOO_SCORING: {}", err)))?;
+    }
+    let mut locs = Vec::new();
+    let mut walker_options = walker::WalkerOptions::default();
+    let mut excludes = Vec::new();
+    let mut includes = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
         if arg.starts_with("--") {
             match &*arg {
                 "--index-all" => index_all = true,
                 "--cd-path" => cd_path = true,
+                "--no-ignore" => walker_options.ignore = false,
+                "--hidden" => SHOW_HIDDEN.store(true, Ordering::Relaxed),
+                "--exclude" => excludes.push(arg_value(&mut args, &arg)?),
+                "--include" => includes.push(arg_value(&mut args, &arg)?),
+                "--follow" => walker_options.follow = true,
+                "--relative" => relative = true,
+                "--read0" => delimiter = b'\0',
+                "--query" => query = arg_value(&mut args, &arg)?,
+                "--select-1" => select_1 = true,
+                "--exit-0" => exit_0 = true,
+                "--print0" => print0 = true,
+                "--ignore-case" => case_matching = CaseMatching::Ignore,
+                "--case-sensitive" => case_matching = CaseMatching::Respect,
+                "--filter" => filter_query = Some(arg_value(&mut args, &arg)?),
+                "--limit" => limit = Some(arg_value(&mut args, &arg)?.parse().map_err(invalid_input)?),
+                "--scoring" => scoring.apply(&arg_value(&mut args, &arg)?).map_err(invalid_input)?,
+                "--tiebreak" => tiebreak = Tiebreak::parse_list(&arg_value(&mut args, &arg)?).map_err(invalid_input)?,
+                "--source-cmd" => source_cmd = Some(arg_value(&mut args, &arg)?),
+                "--one-file-system" => walker_options.one_file_system = true,
+                "--type" => {
+                    let value = arg_value(&mut args, &arg)?;
+                    let bit = type_filter_bit(&value).ok_or_else(|| invalid_input(format!("unknown type: {}", value)))?;
+                    if bit == TYPE_EXECUTABLE {
+                        walker_options.detect_executables = true;
+                    }
+                    TYPE_FILTER.fetch_or(bit, Ordering::Relaxed);
+                }
+                "--max-depth" => walker_options.max_depth = Some(arg_value(&mut args, &arg)?.parse().map_err(invalid_input)?),
+                "--min-depth" => walker_options.min_depth = arg_value(&mut args, &arg)?.parse().map_err(invalid_input)?,
                 _ => (),
             }
         } else {
-            loc = arg;
+            locs.push(arg);
         }
     }
+    walker_options.exclude = GlobFilter::new(&excludes).map_err(invalid_input)?;
+    walker_options.include = GlobFilter::new(&includes).map_err(invalid_input)?;
 
-    let walker = walker::Walker::run(loc);
-    let (cols, rows) = terminal::size().unwrap();
+    let mut type_filters = vec![0, TYPE_FILE, TYPE_DIR, TYPE_SYMLINK];
+    if walker_options.detect_executables {
+        type_filters.push(TYPE_EXECUTABLE);
+    }
+    // explicit sources win over piped stdin, so scripts with a closed or
+    // unrelated stdin can still walk; crossterm reads keys from /dev/tty
+    // when stdin is piped
+    let piped = locs.is_empty() && source_cmd.is_none() && !io::stdin().is_terminal();
+    // the default command only stands in for walking the current directory
+    if source_cmd.is_none() && locs.is_empty() {
+        source_cmd = env::var("BOO_DEFAULT_COMMAND").ok().filter(|cmd| !cmd.trim().is_empty());
+    }
+    if locs.is_empty() {
+        locs.push(".".to_owned());
+    }
+    let mut source = if piped {
+        Source::Lines(line_source::LineSource::run(io::stdin(), delimiter))
+    } else if let Some(cmd) = source_cmd {
+        Source::Lines(line_source::LineSource::command(&cmd, delimiter, filter_query.is_none())?)
+    } else {
+        Source::Walker(walker::Walker::run(locs, walker_options))
+    };
+
+    if let Some(query) = filter_query {
+        let matches = filter_matches(&source, &query, limit.unwrap_or(usize::MAX), case_matching, scoring, &tiebreak);
+        source.wait()?;
+        print_selection(&matches, cd_path, print0)?;
+        return Ok(if matches.is_empty() { EXIT_NO_MATCH } else { EXIT_SELECTED });
+    }
+
+    if select_1 || exit_0 {
+        // two are enough to know it isn't exactly one
+        let matches = filter_matches(&source, &query, 2, case_matching, scoring, &tiebreak);
+        source.wait()?;
+        if select_1 && matches.len() == 1 {
+            print_selection(&matches, cd_path, print0)?;
+            return Ok(EXIT_SELECTED);
+        }
+        if exit_0 && matches.is_empty() {
+            return Ok(EXIT_NO_MATCH);
+        }
+    }
 
-    let top_results = Arc::new(Mutex::new(Collector::new(15)));
-    let mut searcher = Searcher::new(walker.refs.clone(), top_results.clone());
+    let (cols, mut rows) = terminal::size()?;
+
+    let top_results = Arc::new(Mutex::new(Collector::new(limit.unwrap_or(list_rows(rows) * RESULT_PAGES), tiebreak.clone())));
+    let mut searcher = Searcher::new(source.refs().to_vec(), top_results.clone(), case_matching, scoring, tiebreak);
 
     let mut stderr = stderr();
-    terminal::enable_raw_mode().unwrap();
-    execute!(stderr, EnterAlternateScreen).unwrap();
+    terminal::enable_raw_mode()?;
+    let terminal_guard = TerminalGuard;
+    execute!(stderr, EnterAlternateScreen)?;
 
-    stderr.queue(cursor::MoveTo(0, 0)).unwrap();
-    stderr.flush().unwrap();
-    let mut buffer = String::new();
+    stderr.queue(cursor::MoveTo(0, 0))?;
+    stderr.flush()?;
+    let mut buffer = query;
     let mut selection_index = -1;
-
-    let mut final_print: Option<FilePath> = None;
-    let mut cursor_pos = 0;
+    // index of the first result on screen
+    let mut scroll = 0;
+    // only scores the rows on screen again to find what to highlight
+    let mut highlighter = FuzzyMatcher::new(case_matching, scoring);
+
+    let mut final_print: Vec<FilePath> = Vec::new();
+    // marked items in the order they were marked, by path since the collector
+    // is refilled on every search
+    let mut marked: Vec<FilePath> = Vec::new();
+    let mut cancelled = false;
+    let mut cursor_pos = buffer.len();
+    if !buffer.is_empty() {
+        searcher.search(buffer.clone());
+    }
     let mut walking_done = false;
     let mut items = 0;
 
@@ -285,18 +700,31 @@ fn main() -> io::Result<()> {
             break 'mainloop
         }
 
-        let (cols, rows) = terminal::size().unwrap();
+        let (cols, new_rows) = terminal::size()?;
+        if new_rows != rows {
+            rows = new_rows;
+            if limit.is_none() {
+                top_results.lock().unwrap().set_capacity(list_rows(rows) * RESULT_PAGES);
+                // a bigger collector needs a new search to fill up
+                if !buffer.is_empty() {
+                    searcher.search(buffer.clone());
+                }
+            }
+            stderr.queue(terminal::Clear(terminal::ClearType::All))?;
+        }
+        let page = list_rows(rows) as i32;
 
-        if event::poll(Duration::ZERO).unwrap() { 
-            match event::read().unwrap() {
+        if event::poll(Duration::ZERO)? { 
+            match event::read()? {
                 event::Event::Key(key_event) if key_event.kind == event::KeyEventKind::Press => {
                     match key_event.code {
                         event::KeyCode::Char(ch) => {
-                            stderr.queue(terminal::Clear(terminal::ClearType::All)).unwrap();
+                            stderr.queue(terminal::Clear(terminal::ClearType::All))?;
                             if key_event.modifiers.contains(event::KeyModifiers::CONTROL) {
                                 match ch {
                                     'c' => {
                                         SHOULD_QUIT.store(true, Ordering::Relaxed);
+                                        cancelled = true;
                                         break;
                                     }
                                     'n' => {
@@ -305,6 +733,20 @@ fn main() -> io::Result<()> {
                                     'p' => {
                                         selection_index = (selection_index - 1).max(-1);
                                     }
+                                    'h' => {
+                                        SHOW_HIDDEN.fetch_xor(true, Ordering::Relaxed);
+                                        if !buffer.is_empty() {
+                                            searcher.search(buffer.clone());
+                                        }
+                                    }
+                                    't' => {
+                                        let current = TYPE_FILTER.load(Ordering::Relaxed);
+                                        let next = type_filters.iter().position(|filter| *filter == current).map_or(0, |i| type_filters[(i + 1) % type_filters.len()]);
+                                        TYPE_FILTER.store(next, Ordering::Relaxed);
+                                        if !buffer.is_empty() {
+                                            searcher.search(buffer.clone());
+                                        }
+                                    }
                                     _ => (),
                                 }
                             } else {
@@ -325,28 +767,59 @@ fn main() -> io::Result<()> {
                                     searcher.search(buffer.clone());
                                 }
 
-                                stderr.queue(terminal::Clear(terminal::ClearType::All)).unwrap();
+                                stderr.queue(terminal::Clear(terminal::ClearType::All))?;
                             }
                         }
                         event::KeyCode::Enter => {
-                            if selection_index > -1 {
+                            if !marked.is_empty() {
+                                final_print = marked.clone();
+                            } else if selection_index > -1 || !buffer.is_empty() {
+                                // without a selection the top result is picked
                                 let top_results = top_results.lock().unwrap();
-                                let item = top_results.data[selection_index as usize].clone();
+                                let item = top_results.data.get(selection_index.max(0) as usize).copied();
                                 drop(top_results);
 
-                                final_print = Some(item);
+                                final_print.extend(item);
                             }
                             break 'mainloop;
                         }
-                        event::KeyCode::Left => {
-                            if cursor_pos > 0 {
-                                cursor_pos -= 1;
+                        event::KeyCode::Tab | event::KeyCode::BackTab if selection_index > -1 => {
+                            let top_results = top_results.lock().unwrap();
+                            let item = top_results.data.get(selection_index as usize).copied();
+                            drop(top_results);
+
+                            if let Some(item) = item {
+                                if let Some(position) = marked.iter().position(|m| m.data == item.data) {
+                                    marked.remove(position);
+                                } else {
+                                    marked.push(item);
+                                }
+                                if key_event.code == event::KeyCode::Tab {
+                                    selection_index += 1;
+                                } else {
+                                    selection_index = (selection_index - 1).max(0);
+                                }
                             }
+                            stderr.queue(terminal::Clear(terminal::ClearType::All))?;
                         }
-                        event::KeyCode::Right => {
-                            if cursor_pos < buffer.len() {
-                                cursor_pos += 1;
-                            }
+                        event::KeyCode::Left => {
+                            cursor_pos = cursor_pos.saturating_sub(1);
+                        }
+                        event::KeyCode::Right if cursor_pos < buffer.len() => {
+                            cursor_pos += 1;
+                        }
+                        event::KeyCode::PageDown => {
+                            selection_index = selection_index.max(0) + page;
+                        }
+                        event::KeyCode::PageUp if selection_index > -1 => {
+                            selection_index = (selection_index - page).max(0);
+                        }
+                        event::KeyCode::Home => {
+                            selection_index = 0;
+                        }
+                        event::KeyCode::End => {
+                            // clamped to the last result below
+                            selection_index = i32::MAX;
                         }
                         _ => (),
                     }
@@ -355,8 +828,20 @@ fn main() -> io::Result<()> {
             }
         }
 
+        let results = top_results.lock().unwrap().data.len() as i32;
+        selection_index = selection_index.min(results - 1);
+        if selection_index > -1 {
+            let selected = selection_index as usize;
+            if selected < scroll {
+                scroll = selected;
+            } else if selected >= scroll + page as usize {
+                scroll = selected + 1 - page as usize;
+            }
+        }
+        scroll = scroll.min((results - page).max(0) as usize);
+
         if !walking_done {
-            items = walker.refs.iter().map(|r| r.read().len()).sum();
+            items = source.refs().iter().map(|r| r.read().len()).sum();
         }
         if !walking_done && WALKER_THREADS.load(Ordering::Relaxed) == 0 {
             walking_done = true;
@@ -365,12 +850,12 @@ fn main() -> io::Result<()> {
         queue!(
             stderr,
             cursor::MoveTo(0, 0),
-            Print(format!("{}", buffer)),
+            Print(&buffer),
             cursor::MoveTo(0, 1),
-            Print(format!("items: {}, walker threads: {}, search workers: {}", items, WALKER_THREADS.load(Ordering::Relaxed), SEARCH_WORKERS.load(Ordering::Relaxed))),
+            Print(format!("items: {}, walker threads: {}, search workers: {}, hidden: {}, type: {}, marked: {}", items, WALKER_THREADS.load(Ordering::Relaxed), SEARCH_WORKERS.load(Ordering::Relaxed), if SHOW_HIDDEN.load(Ordering::Relaxed) { "shown" } else { "skipped" }, type_filter_name(TYPE_FILTER.load(Ordering::Relaxed)), marked.len())),
             cursor::MoveTo(0, 2),
-        ).unwrap();
-        stderr.flush().unwrap();
+        )?;
+        stderr.flush()?;
 
         if !buffer.is_empty() {
             let top_results = top_results.lock().unwrap();
@@ -378,36 +863,81 @@ fn main() -> io::Result<()> {
             let points = top_results.points.clone();
             drop(top_results);
 
-            for (index, item) in items.iter().enumerate() {
-                stderr.queue(cursor::MoveTo(0, 2 + index as u16)).unwrap();
-                if selection_index == index as i32 {
-                    write!(stderr, "{}{}{}", item.name().white().on_black(), " --> ".white().on_black(), item.path().white().on_black()).unwrap();
+            let highlight_query = Query::parse(&buffer);
+            let visible = items.iter().enumerate().skip(scroll).take(page as usize);
+            for (row, (index, item)) in visible.enumerate() {
+                stderr.queue(cursor::MoveTo(0, HEADER_ROWS + row as u16))?;
+                let roots = source.roots();
+                let root = roots.get(item.root as usize).map_or("", |root| root.as_str());
+                let path = if relative { item.relative_path(root) } else { item.path() };
+                // only worth telling roots apart when there are several of them
+                let root_tag = if roots.len() > 1 { format!(" [{}]", root) } else { String::new() };
+                let mark = if marked.iter().any(|m| m.data == item.data) { "* " } else { "  " };
+                let positions = highlight_query.match_positions(&mut highlighter, *item);
+                let selected = selection_index == index as i32;
+                let name = highlight(item.name(), item.data.len() - item.name().len(), &positions, selected);
+                let path = highlight(path, item.data.len() - path.len(), &positions, selected);
+                if selected {
+                    write!(stderr, "{}{}{}{}{}", mark.white().on_black(), name, " --> ".white().on_black(), path, root_tag.white().on_black())?;
                 } else {
-                    write!(stderr, "{}{}{}", item.name(), " --> ", item.path()).unwrap();
+                    write!(stderr, "{}{} --> {}{}", mark.green(), name, path, root_tag.dark_grey())?;
                 }
+                stderr.queue(terminal::Clear(terminal::ClearType::UntilNewLine))?;
             }
+            // rows left over from a longer list or a different scroll position
+            let drawn = items.len().saturating_sub(scroll).min(page as usize);
+            queue!(stderr, cursor::MoveTo(0, HEADER_ROWS + drawn as u16), terminal::Clear(terminal::ClearType::FromCursorDown))?;
         }
 
-        stderr.queue(cursor::MoveTo(cursor_pos as u16, 0)).unwrap();
-        stderr.flush().unwrap();
+        stderr.queue(cursor::MoveTo(cursor_pos as u16, 0))?;
+        stderr.flush()?;
         thread::sleep(Duration::from_millis(1000 / 30))
     }
     
     SHOULD_QUIT.store(true, Ordering::Relaxed);
-    stderr.execute(Clear(terminal::ClearType::All)).unwrap();
-    execute!(stderr, LeaveAlternateScreen).unwrap();
-    terminal::disable_raw_mode().unwrap();
+    drop(terminal_guard);
     searcher.terminate();
-
-    if let Some(item) = final_print {
-        if cd_path {
-            println!("{}", item.containing_folder());
-        } else {
-            println!("{}", item.to_string());
-        }
+    source.wait()?;
+
+    if !final_print.is_empty() {
+        print_selection(&final_print, cd_path, print0)?;
+        Ok(EXIT_SELECTED)
+    } else if cancelled {
+        Ok(EXIT_CANCELLED)
+    } else if index_all {
+        Ok(EXIT_SELECTED)
     } else {
-        println!("boo done :3");
+        Ok(EXIT_NO_MATCH)
     }
+}
 
-    Ok(())
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_before_items() {
+        let storage = Arc::new(parking_lot::RwLock::new(Arena::new()));
+        let tiebreak = tiebreak::DEFAULT_TIEBREAK.to_vec();
+        let collector = Arc::new(Mutex::new(Collector::new(10, tiebreak.clone())));
+        let mut searcher = Searcher::new(vec![storage.clone()], collector.clone(), CaseMatching::Smart, ScoringConfig::default(), tiebreak);
+
+        // like --query, searched before the source produced anything
+        searcher.search("foo".to_owned());
+        thread::sleep(Duration::from_millis(50));
+        storage.write().extend(&[FilePath::new(b"bar"), FilePath::new(b"foo")]);
+
+        for _ in 0..1000 {
+            if !collector.lock().unwrap().data.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        SHOULD_QUIT.store(true, Ordering::Relaxed);
+        searcher.terminate();
+
+        let paths = collector.lock().unwrap().data.iter().map(|item| item.path().to_owned()).collect::<Vec<_>>();
+        assert_eq!(paths, ["foo"]);
+    }
 }
@@ -0,0 +1,328 @@
+use crate::{byte_storage::{letter_mask, FilePath}, fuzzy_match::FuzzyMatcher};
+
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum TermKind {
+    Fuzzy,
+    /// `'word`
+    Exact,
+    /// `^word`
+    Prefix,
+    /// `word$`
+    Suffix,
+    /// `^word$`
+    Equal,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+struct Term {
+    kind: TermKind,
+    /// `!word`, the item must not match
+    negated: bool,
+    text: Vec<u8>,
+    /// `letter_mask` of the text, a path missing any of these can't match
+    letters: u64,
+}
+
+/// fzf style extended search: space separated terms that all have to match,
+/// where `a | b` matches either of the two.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Query {
+    /// every group has to match, any term inside a group is enough
+    groups: Vec<Vec<Term>>,
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Self {
+        let mut groups: Vec<Vec<Term>> = Vec::new();
+        let mut join_next = false;
+        for token in split_terms(input) {
+            if token == "|" {
+                join_next = !groups.is_empty();
+                continue;
+            }
+            let Some(term) = Term::parse(&token) else { continue };
+            match groups.last_mut() {
+                Some(group) if join_next => group.push(term),
+                _ => groups.push(vec![term]),
+            }
+            join_next = false;
+        }
+
+        Self {
+            groups,
+        }
+    }
+
+    /// Whether every item matching `self` also matches `previous`, e.g. when
+    /// a letter was typed at the end of the query.
+    pub fn narrows(&self, previous: &Query) -> bool {
+        self.groups.len() >= previous.groups.len()
+            && self.groups.iter().zip(&previous.groups).all(|(group, previous)| match (&group[..], &previous[..]) {
+                ([term], [previous]) => term.narrows(previous),
+                // extending an alternative adds matches
+                _ => group == previous,
+            })
+    }
+
+    /// `None` if the item doesn't match, otherwise the sum of the best score of every group.
+    pub fn score(&self, matcher: &mut FuzzyMatcher, item: FilePath) -> Option<i32> {
+        let mut total: i32 = 0;
+        for group in &self.groups {
+            let best = group.iter().filter_map(|term| term.score(matcher, item)).max()?;
+            total = total.saturating_add(best);
+        }
+        Some(total)
+    }
+
+    /// Byte positions in the path matched by the positive terms, ascending.
+    pub fn match_positions(&self, matcher: &mut FuzzyMatcher, item: FilePath) -> Vec<usize> {
+        let mut positions = Vec::new();
+        for term in self.groups.iter().flatten().filter(|term| !term.negated) {
+            match term.kind {
+                TermKind::Fuzzy => positions.extend(matcher.match_positions(&term.text, item)),
+                _ => {
+                    let ignore_case = matcher.ignores_case(&term.text);
+                    if let Some(start) = term.find(item.data, ignore_case) {
+                        positions.extend(start..start + term.text.len());
+                    }
+                }
+            }
+        }
+        positions.sort_unstable();
+        positions.dedup();
+        positions
+    }
+}
+
+impl Term {
+    fn parse(token: &str) -> Option<Self> {
+        let mut text = token;
+        let negated = text.starts_with('!');
+        if negated {
+            text = &text[1..];
+        }
+
+        let kind = if let Some(rest) = text.strip_prefix('\'') {
+            text = rest;
+            TermKind::Exact
+        } else {
+            let prefix = text.strip_prefix('^');
+            if let Some(rest) = prefix {
+                text = rest;
+            }
+            let suffix = text.strip_suffix('$').filter(|rest| !rest.is_empty());
+            if let Some(rest) = suffix {
+                text = rest;
+            }
+            match (prefix.is_some(), suffix.is_some()) {
+                (true, true) => TermKind::Equal,
+                (true, false) => TermKind::Prefix,
+                (false, true) => TermKind::Suffix,
+                // a negated word is an exact match like in fzf, "!abc" reads as "not containing abc"
+                (false, false) if negated => TermKind::Exact,
+                (false, false) => TermKind::Fuzzy,
+            }
+        };
+
+        if text.is_empty() {
+            return None;
+        }
+        Some(Self {
+            kind,
+            negated,
+            text: text.as_bytes().to_vec(),
+            letters: letter_mask(text.as_bytes()),
+        })
+    }
+
+    fn narrows(&self, previous: &Term) -> bool {
+        if self == previous {
+            return true;
+        }
+        // a longer negated term rejects less
+        if self.negated || previous.negated || !self.text.starts_with(&previous.text) {
+            return false;
+        }
+        match (previous.kind, self.kind) {
+            // every other kind contains the text as is, so also as a subsequence
+            (TermKind::Fuzzy, _) => true,
+            (TermKind::Exact, kind) => kind != TermKind::Fuzzy,
+            (TermKind::Prefix, kind) => matches!(kind, TermKind::Prefix | TermKind::Equal),
+            // "abc$" doesn't end with "ab"
+            (TermKind::Suffix | TermKind::Equal, _) => false,
+        }
+    }
+
+    /// `None` if the term rejects the item.
+    fn score(&self, matcher: &mut FuzzyMatcher, item: FilePath) -> Option<i32> {
+        if self.negated {
+            if item.letters & self.letters != self.letters {
+                return Some(0);
+            }
+            let ignore_case = matcher.ignores_case(&self.text);
+            return match self.find(item.data, ignore_case) {
+                Some(_) => None,
+                None => Some(0),
+            };
+        }
+
+        // every kind of term needs all of its bytes in the path, much cheaper
+        // to rule out than running the matcher
+        if item.letters & self.letters != self.letters {
+            return None;
+        }
+        if self.kind != TermKind::Fuzzy {
+            let ignore_case = matcher.ignores_case(&self.text);
+            self.find(item.data, ignore_case)?;
+        }
+        // exact terms are still scored like fuzzy ones so they rank the same way
+        let score = matcher.smith_waterman(&self.text, item);
+        (score > 0).then_some(score)
+    }
+
+    /// Start of the match in `path`, fuzzy terms aren't looked up here.
+    fn find(&self, path: &[u8], ignore_case: bool) -> Option<usize> {
+        // paths walked from "." all start with "./", anchors skip it
+        let skipped = if path.starts_with(b"./") { 2 } else { 0 };
+        let anchored = &path[skipped..];
+        let text = &self.text[..];
+        let eq = |a: &[u8], b: &[u8]| if ignore_case { a.eq_ignore_ascii_case(b) } else { a == b };
+
+        match self.kind {
+            TermKind::Fuzzy => None,
+            TermKind::Exact => path.windows(text.len()).position(|window| eq(window, text)),
+            TermKind::Prefix => (anchored.len() >= text.len() && eq(&anchored[..text.len()], text)).then_some(skipped),
+            TermKind::Suffix => (path.len() >= text.len() && eq(&path[path.len() - text.len()..], text)).then_some(path.len() - text.len()),
+            TermKind::Equal => eq(anchored, text).then_some(skipped),
+        }
+    }
+}
+
+/// Splits on whitespace, `\ ` is a literal space.
+fn split_terms(input: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    let mut current = String::new();
+    let mut chars = input.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' && chars.peek() == Some(&' ') {
+            current.push(' ');
+            chars.next();
+        } else if ch.is_whitespace() {
+            if !current.is_empty() {
+                terms.push(std::mem::take(&mut current));
+            }
+        } else {
+            current.push(ch);
+        }
+    }
+    if !current.is_empty() {
+        terms.push(current);
+    }
+    terms
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fuzzy_match::{CaseMatching, ScoringConfig}, test_util::XorShift};
+
+    fn matches(query: &str, path: &'static str) -> bool {
+        let mut matcher = FuzzyMatcher::new(CaseMatching::Smart, ScoringConfig::default());
+        Query::parse(query).score(&mut matcher, FilePath::new(path.as_bytes())).is_some()
+    }
+
+    #[test]
+    fn test_parse() {
+        let query = Query::parse("^src 'main !test rs$ | toml");
+        let kinds = query.groups.iter().map(|group| group.iter().map(|term| (term.kind, term.negated)).collect::<Vec<_>>()).collect::<Vec<_>>();
+        assert_eq!(kinds, vec![
+            vec![(TermKind::Prefix, false)],
+            vec![(TermKind::Exact, false)],
+            vec![(TermKind::Exact, true)],
+            vec![(TermKind::Suffix, false), (TermKind::Fuzzy, false)],
+        ]);
+        assert!(Query::parse("  ").groups.is_empty());
+    }
+
+    #[test]
+    fn test_terms() {
+        assert!(matches("^src .rs$", "./src/main.rs"));
+        assert!(!matches("^main", "./src/main.rs"));
+        assert!(matches("'main", "./src/main.rs"));
+        assert!(!matches("'mian", "./src/main.rs"));
+        assert!(!matches("main !src", "./src/main.rs"));
+        assert!(matches("main !test", "./src/main.rs"));
+        assert!(matches(".toml$ | .rs$", "./src/main.rs"));
+        assert!(!matches(".toml$ | .lock$", "./src/main.rs"));
+        assert!(matches("!test", "./src/main.rs"));
+    }
+
+    #[test]
+    fn test_narrows() {
+        let narrows = |query: &str, previous: &str| Query::parse(query).narrows(&Query::parse(previous));
+
+        assert!(narrows("mai", "ma"));
+        assert!(narrows("main rs", "main"));
+        assert!(narrows("main$", "main"));
+        assert!(narrows("^src$", "^src"));
+        assert!(narrows("'main", "'mai"));
+        assert!(narrows("!test main", "!test"));
+        assert!(narrows("a | b", "a | b"));
+        assert!(narrows("mainR", "main"));
+        assert!(!narrows("ma", "mai"));
+        assert!(!narrows("!tests", "!test"));
+        assert!(!narrows("rs$c", "rs$"));
+        assert!(!narrows("a | b", "a |"));
+        assert!(!narrows("a\\ b", "a\\"));
+        assert!(!narrows("nain", "main"));
+    }
+
+    /// `cargo test --release -- --ignored --nocapture bench_prefilter`
+    #[test]
+    #[ignore]
+    fn bench_prefilter() {
+        const PATHS: usize = 1_000_000;
+        const WORDS: [&str; 16] = ["src", "lib", "core", "target", "debug", "node_modules", "test", "utils", "config", "main", "index", "build", "assets", "docs", "vendor", "Cargo"];
+        const EXTENSIONS: [&str; 6] = ["rs", "js", "toml", "md", "json", "txt"];
+
+        // a deterministic tree of a million paths, leaked since paths are 'static
+        let mut rng = XorShift::new(0x2545_f491_4f6c_dd1d);
+        let mut next = |n: usize| rng.below(n);
+        let mut text = String::new();
+        let mut ends = Vec::with_capacity(PATHS);
+        for _ in 0..PATHS {
+            text.push('.');
+            for _ in 0..1 + next(8) {
+                text.push('/');
+                text.push_str(WORDS[next(WORDS.len())]);
+            }
+            text.push_str(&format!("_{}.{}", next(1000), EXTENSIONS[next(EXTENSIONS.len())]));
+            ends.push(text.len());
+        }
+        let text: &'static str = Box::leak(text.into_boxed_str());
+        let items = ends.iter().scan(0, |start, end| {
+            let item = FilePath::new(&text.as_bytes()[*start..*end]);
+            *start = *end;
+            Some(item)
+        }).collect::<Vec<_>>();
+
+        let mut matcher = FuzzyMatcher::new(CaseMatching::Smart, ScoringConfig::default());
+        for input in ["mainrs", "zq", "vndrjs", "cfgjson"] {
+            let query = Query::parse(input);
+            let mut unfiltered = query.clone();
+            unfiltered.groups.iter_mut().flatten().for_each(|term| term.letters = 0);
+
+            let start = std::time::Instant::now();
+            let matched = items.iter().filter(|item| query.score(&mut matcher, **item).is_some()).count();
+            let filtered = start.elapsed();
+            let start = std::time::Instant::now();
+            let matched_unfiltered = items.iter().filter(|item| unfiltered.score(&mut matcher, **item).is_some()).count();
+            let full = start.elapsed();
+
+            assert_eq!(matched, matched_unfiltered);
+            println!("{:>8}: {:>7} matches, prefilter {:>8.1?}, full scan {:>8.1?}", input, matched, filtered, full);
+        }
+    }
+}
@@ -0,0 +1,19 @@
+//! Helpers shared by the tests of several modules.
+
+/// Xorshift numbers, the same on every run so generated test data is too.
+pub struct XorShift(u64);
+
+impl XorShift {
+    /// `seed` must not be 0.
+    pub fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    /// A number in `0..n`.
+    pub fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 as usize % n
+    }
+}
@@ -0,0 +1,89 @@
+use std::cmp::Ordering;
+
+use crate::byte_storage::FilePath;
+
+
+/// Decides the order of results with the same score, see `--tiebreak`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Tiebreak {
+    /// shorter paths first
+    Length,
+    /// fewer path segments first
+    Depth,
+    /// the order the source produced the items in, for walked entries root
+    /// by root and shallower first like a breadth-first walk
+    Index,
+    /// file names in byte order
+    Name,
+}
+
+pub const DEFAULT_TIEBREAK: [Tiebreak; 2] = [Tiebreak::Length, Tiebreak::Depth];
+
+impl Tiebreak {
+    /// Parses a comma separated list like `length,depth`.
+    pub fn parse_list(list: &str) -> Result<Vec<Self>, String> {
+        list.split(',')
+            .map(|name| match name.trim() {
+                "length" => Ok(Tiebreak::Length),
+                "depth" => Ok(Tiebreak::Depth),
+                "index" => Ok(Tiebreak::Index),
+                "name" => Ok(Tiebreak::Name),
+                other => Err(format!("unknown tiebreak: {}", other)),
+            })
+            .collect()
+    }
+
+    fn compare(self, a: &FilePath, b: &FilePath) -> Ordering {
+        match self {
+            Tiebreak::Length => a.data.len().cmp(&b.data.len()),
+            Tiebreak::Depth => depth(a).cmp(&depth(b)),
+            // walked entries are all index 0, the walker threads finish directories in any order
+            Tiebreak::Index => a.index.cmp(&b.index).then(a.root.cmp(&b.root)).then_with(|| depth(a).cmp(&depth(b))),
+            Tiebreak::Name => a.name().cmp(b.name()),
+        }
+    }
+}
+
+/// `Less` if `a` goes before `b`. Falls back to comparing the whole path so
+/// the order never depends on which search worker reported an item first.
+pub fn compare(tiebreak: &[Tiebreak], a: &FilePath, b: &FilePath) -> Ordering {
+    tiebreak
+        .iter()
+        .map(|criterion| criterion.compare(a, b))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| a.data.cmp(b.data))
+}
+
+fn depth(item: &FilePath) -> usize {
+    item.data.iter().filter(|ch| **ch == b'/').count()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let short = FilePath::new(b"./src/a/main.rs");
+        let shallow = FilePath::new(b"./src/main_old.rs");
+        let mut first = FilePath::new(b"./src/b/main.rs");
+        first.index = 0;
+        let mut second = short;
+        second.index = 1;
+
+        assert_eq!(compare(&DEFAULT_TIEBREAK, &short, &shallow), Ordering::Less);
+        assert_eq!(compare(&[Tiebreak::Depth], &short, &shallow), Ordering::Greater);
+        assert_eq!(compare(&[Tiebreak::Index], &first, &second), Ordering::Less);
+        // walked entries, same order whichever directory was read first
+        let mut other_root = FilePath::new(b"./a.rs");
+        other_root.root = 1;
+        assert_eq!(compare(&[Tiebreak::Index], &other_root, &shallow), Ordering::Greater);
+        assert_eq!(compare(&[Tiebreak::Index], &shallow, &short), Ordering::Less);
+        assert_eq!(compare(&[Tiebreak::Index], &short, &FilePath::new(b"./src/b/a.rs")), Ordering::Less);
+        // equal on every criterion, still ordered
+        assert_eq!(compare(&[Tiebreak::Name], &short, &first), Ordering::Less);
+        assert_eq!(Tiebreak::parse_list("name,index"), Ok(vec![Tiebreak::Name, Tiebreak::Index]));
+        assert!(Tiebreak::parse_list("size").is_err());
+    }
+}
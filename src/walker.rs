@@ -1,24 +1,157 @@
-use std::{os::unix::ffi::OsStrExt, path::PathBuf, str::FromStr, sync::{atomic::Ordering, Arc}, thread::{self, available_parallelism, JoinHandle}};
+use std::{collections::HashSet, os::unix::{ffi::OsStrExt, fs::{MetadataExt, PermissionsExt}}, path::{Path, PathBuf}, str::FromStr, sync::{atomic::{AtomicUsize, Ordering}, Arc}, thread::{self, available_parallelism, JoinHandle}};
 use std::collections::VecDeque;
 
 use walkdir::WalkDir;
 
-use crate::{arena::Arena, byte_storage::FilePath, WALKER_THREADS};
+use crate::{arena::Arena, byte_storage::{FileKind, FilePath}, glob_filter::GlobFilter, ignore_rules::IgnoreRules, WALKER_THREADS};
 
 
+// mount points of pseudo filesystems that are never descended into
+const PSEUDO_FILESYSTEMS: [&str; 3] = ["/proc", "/sys", "/dev"];
+
+pub struct WalkerOptions {
+    /// honor .gitignore, .ignore, .git/info/exclude and the global excludes file
+    pub ignore: bool,
+    /// matching entries are skipped and matching directories are not descended into
+    pub exclude: GlobFilter,
+    /// when not empty only matching entries are indexed, directories are still descended into
+    pub include: GlobFilter,
+    /// entries deeper than this are not indexed, the root's entries are at depth 1
+    pub max_depth: Option<usize>,
+    /// entries shallower than this are not indexed but still descended into
+    pub min_depth: usize,
+    /// descend into symlinked directories
+    pub follow: bool,
+    /// don't descend into directories on a different device than the walk root
+    pub one_file_system: bool,
+    /// stat regular files to tell executables apart, costs a syscall per file
+    pub detect_executables: bool,
+}
+
+impl Default for WalkerOptions {
+    fn default() -> Self {
+        Self {
+            ignore: true,
+            exclude: GlobFilter::empty(),
+            include: GlobFilter::empty(),
+            max_depth: None,
+            min_depth: 0,
+            follow: false,
+            one_file_system: false,
+            detect_executables: false,
+        }
+    }
+}
+
+/// A directory passed to `Walker::run`, shared by every job below it.
+struct Root {
+    path: PathBuf,
+    index: u16,
+    /// device of the root, only set with `one_file_system`
+    device: Option<u64>,
+}
+
+struct Job {
+    path: PathBuf,
+    root: Arc<Root>,
+    /// depth of the directory itself, 0 for the walk root
+    depth: usize,
+    /// rules of the parent directory, `None` when ignore files are disabled
+    ignore: Option<Arc<IgnoreRules>>,
+    /// the directory itself is hidden or inside a hidden directory
+    hidden: bool,
+}
+
+/// State shared by all walker threads.
+struct Shared {
+    options: WalkerOptions,
+    /// (dev, inode) of every directory queued so far, only used with `track_visited`
+    visited: parking_lot::Mutex<HashSet<(u64, u64)>>,
+    /// a directory can be reached more than once, through a followed link or
+    /// from a root that contains another root
+    track_visited: bool,
+    /// (dev, inode) of the mounted `PSEUDO_FILESYSTEMS`
+    pseudo_filesystems: Vec<(u64, u64)>,
+    /// jobs queued or still being walked, the walk is over once it reaches 0
+    pending: AtomicUsize,
+}
+
+impl Shared {
+    fn should_descend(&self, entry: &walkdir::DirEntry, root: &Root) -> bool {
+        let maybe_pseudo = PSEUDO_FILESYSTEMS.iter().any(|dir| Path::new(dir).file_name() == Some(entry.file_name()));
+        if !maybe_pseudo && !self.track_visited && root.device.is_none() {
+            return true;
+        }
+
+        let Ok(metadata) = entry.metadata() else { return false };
+        let id = (metadata.dev(), metadata.ino());
+        if maybe_pseudo && self.pseudo_filesystems.contains(&id) {
+            return false;
+        }
+        if root.device.is_some_and(|device| device != metadata.dev()) {
+            return false;
+        }
+        // returns false if the directory was already queued through another path,
+        // e.g. a symlink back to one of its parents or a nested root
+        !self.track_visited || self.visited.lock().insert(id)
+    }
+}
+
 pub struct Walker {
+    pub roots: Vec<String>,
     pub refs: Vec<Arc<parking_lot::RwLock<Arena<FilePath>>>>,
     handles: Vec<JoinHandle<Arena<u8>>>,
 }
 
 impl Walker {
-    pub fn run(start: String) -> Self {
+    pub fn run(roots: Vec<String>, options: WalkerOptions) -> Self {
         let mut job_stack = VecDeque::new();
         let threads = available_parallelism().unwrap().get();
         WALKER_THREADS.store(threads as u32, Ordering::Relaxed);
         let mut handles = vec![];
 
-        job_stack.push_back(PathBuf::from_str(&start).unwrap());
+        let mut visited = HashSet::new();
+        for (index, start) in roots.iter().enumerate() {
+            let path = PathBuf::from_str(start).unwrap();
+            let readable = std::fs::metadata(&path).and_then(|metadata| {
+                if metadata.is_dir() {
+                    std::fs::read_dir(&path)?;
+                }
+                Ok(metadata)
+            });
+            let metadata = match readable {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    eprintln!("boo: {}: {}", start, err);
+                    continue;
+                }
+            };
+            // the same directory given twice is only walked once
+            if !visited.insert((metadata.dev(), metadata.ino())) {
+                continue;
+            }
+
+            let ignore = options.ignore.then(|| IgnoreRules::new(&path));
+            let root = Arc::new(Root {
+                path: path.clone(),
+                index: index as u16,
+                device: options.one_file_system.then(|| metadata.dev()),
+            });
+            job_stack.push_back(Job { path, root, depth: 0, ignore, hidden: false });
+        }
+
+        let pseudo_filesystems = PSEUDO_FILESYSTEMS
+            .iter()
+            .filter_map(|dir| std::fs::metadata(dir).ok())
+            .map(|metadata| (metadata.dev(), metadata.ino()))
+            .collect();
+        let shared = Arc::new(Shared {
+            visited: parking_lot::Mutex::new(visited),
+            track_visited: options.follow || job_stack.len() > 1,
+            pseudo_filesystems,
+            pending: AtomicUsize::new(job_stack.len()),
+            options,
+        });
 
         let job_stack = Arc::new(parking_lot::Mutex::new(job_stack));
         let mut refs_storages = vec![];
@@ -29,11 +162,12 @@ impl Walker {
             let ref_storage = Arc::new(parking_lot::RwLock::new(Arena::new()));
             refs_storages.push(ref_storage.clone());
 
-            let handle = spawn(job_stack, ref_storage);
+            let handle = spawn(job_stack, ref_storage, shared.clone());
             handles.push(handle);
         }
 
         Self {
+            roots,
             refs: refs_storages,
             handles,
         }
@@ -41,55 +175,155 @@ impl Walker {
 
 }
 
-fn spawn(job_stack: Arc<parking_lot::Mutex<VecDeque<PathBuf>>>, ref_storage: Arc<parking_lot::RwLock<Arena<FilePath>>>) -> JoinHandle<Arena<u8>> {
+fn spawn(job_stack: Arc<parking_lot::Mutex<VecDeque<Job>>>, ref_storage: Arc<parking_lot::RwLock<Arena<FilePath>>>, shared: Arc<Shared>) -> JoinHandle<Arena<u8>> {
     let handle = thread::spawn(move || {
+        let options = &shared.options;
         let mut arena = Arena::new();
-        let mut ready_to_quit = false;
         loop {
             let mut stack = job_stack.lock();
-            let dent = stack.pop_front();
+            let job = stack.pop_front();
             drop(stack);
 
-            if let Some(dent) = dent {
-                if ready_to_quit {
-                    ready_to_quit = false;
-                    WALKER_THREADS.fetch_add(1, Ordering::Relaxed);
+            if let Some(job) = job {
+                let depth = job.depth + 1;
+                let max_depth = options.max_depth.unwrap_or(usize::MAX);
+                if depth > max_depth {
+                    shared.pending.fetch_sub(1, Ordering::AcqRel);
+                    continue;
                 }
+                let descend = depth < max_depth;
+
                 let mut dir_items = vec![];
                 let mut next_folders = vec![];
+                let ignore = job.ignore.map(|rules| rules.child(&job.path));
 
-                for entry in WalkDir::new(dent).min_depth(1).max_depth(1).follow_root_links(false) {
+                for entry in WalkDir::new(&job.path).min_depth(1).max_depth(1).follow_root_links(false).follow_links(options.follow) {
                     let Ok(entry) = entry else { continue; };
+                    let is_dir = entry.file_type().is_dir();
+                    if ignore.as_ref().is_some_and(|rules| rules.is_ignored(entry.path(), is_dir)) {
+                        continue;
+                    }
 
-                    let slice = arena.extend_and_get(entry.path().as_os_str().as_bytes());
-                    dir_items.push(FilePath::new(slice));
+                    let relative = entry.path().strip_prefix(&job.root.path).unwrap_or(entry.path());
+                    if !options.exclude.is_empty() && options.exclude.is_match(relative, entry.file_name()) {
+                        continue;
+                    }
+                    let included = depth >= options.min_depth
+                        && (options.include.is_empty() || options.include.is_match(relative, entry.file_name()));
+
+                    // hidden entries are still indexed so they can be toggled on without walking again
+                    let hidden = job.hidden || entry.file_name().as_bytes().starts_with(b".");
+
+                    if included {
+                        let slice = arena.extend_and_get(entry.path().as_os_str().as_bytes());
+                        let mut file_path = FilePath::new(slice);
+                        file_path.hidden = hidden;
+                        file_path.kind = file_kind(&entry, options.detect_executables);
+                        file_path.root = job.root.index;
+                        dir_items.push(file_path);
+                    }
 
-                    if entry.file_type().is_dir() {
+                    if is_dir && descend && shared.should_descend(&entry, &job.root) {
                     // if entry.path().is_dir() {
-                        next_folders.push(entry.path().to_owned());
+                        next_folders.push(Job { path: entry.path().to_owned(), root: job.root.clone(), depth, ignore: ignore.clone(), hidden });
                     }
                 }
 
+                // counted before they're queued so pending can't hit 0 in between
+                shared.pending.fetch_add(next_folders.len(), Ordering::AcqRel);
                 let mut stack = job_stack.lock();
                 stack.extend(next_folders);
                 drop(stack);
-                if dir_items.len() > 0 {
+                // no `FilePath::index`, the threads finish directories in any
+                // order so the index tiebreak goes by root and depth instead
+                if !dir_items.is_empty() {
                     ref_storage.write().extend(&dir_items);
                 }
                 //byte_storage.extend(dir_items);
-            } else {
-                if ready_to_quit && WALKER_THREADS.load(Ordering::Relaxed) == 0 {
-                    break;
-                }
-                if !ready_to_quit {
-                    ready_to_quit = true;
-                    WALKER_THREADS.fetch_sub(1, Ordering::Relaxed);
-                }
+                shared.pending.fetch_sub(1, Ordering::AcqRel);
+            } else if shared.pending.load(Ordering::Acquire) == 0 {
+                break;
             }
         }
 
+        // everything this thread found is in its storage by now
+        WALKER_THREADS.fetch_sub(1, Ordering::Release);
         arena
     });
 
     handle
 }
+
+fn file_kind(entry: &walkdir::DirEntry, detect_executables: bool) -> FileKind {
+    let file_type = entry.file_type();
+    // with --follow walkdir reports the type of the link's target
+    if entry.path_is_symlink() {
+        FileKind::Symlink
+    } else if file_type.is_dir() {
+        FileKind::Dir
+    } else if !file_type.is_file() {
+        FileKind::Other
+    } else if detect_executables && entry.metadata().is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0) {
+        FileKind::Executable
+    } else {
+        FileKind::File
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    /// Every path the walk indexed, sorted.
+    fn walk(roots: &[&Path], options: WalkerOptions) -> Vec<String> {
+        let roots = roots.iter().map(|root| root.to_str().unwrap().to_owned()).collect();
+        let Walker { refs, handles, .. } = Walker::run(roots, options);
+        // the returned arenas hold the path bytes, kept until the paths are copied
+        let arenas = handles.into_iter().map(|handle| handle.join().unwrap()).collect::<Vec<_>>();
+        let mut paths = refs
+            .iter()
+            .flat_map(|storage| storage.read().read_only_view())
+            .flatten()
+            .map(|item| item.path().to_owned())
+            .collect::<Vec<_>>();
+        drop(arenas);
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_nested_roots() {
+        let root = std::env::temp_dir().join(format!("boo_nested_roots_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src/sub")).unwrap();
+        fs::write(root.join("src/sub/lib.rs"), "").unwrap();
+
+        let paths = walk(&[&root.join("src"), &root.join("src/sub"), &root], WalkerOptions::default());
+        let lib = paths.iter().filter(|path| path.ends_with("lib.rs")).collect::<Vec<_>>();
+        // the innermost root walks it
+        assert_eq!(lib, [&format!("{}/src/sub/lib.rs", root.display())]);
+        assert_eq!(paths.len(), 3);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_symlink_loop() {
+        let root = std::env::temp_dir().join(format!("boo_symlink_loop_test_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/file.rs"), "").unwrap();
+        std::os::unix::fs::symlink("..", root.join("a/b/loop")).unwrap();
+
+        let options = WalkerOptions { follow: true, ..WalkerOptions::default() };
+        let paths = walk(&[&root], options);
+        let relative = paths.iter().map(|path| path.strip_prefix(root.to_str().unwrap()).unwrap()).collect::<Vec<_>>();
+        // the link is listed but not walked again
+        assert_eq!(relative, ["/a", "/a/b", "/a/b/file.rs", "/a/b/loop"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}